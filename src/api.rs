@@ -0,0 +1,229 @@
+use axum::extract::rejection::JsonRejection;
+use axum::extract::Path;
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use futures::{stream, Stream, StreamExt};
+use serde::Serialize;
+use serde_json::json;
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use crate::cache::RepoCache;
+use crate::cancel::CancelToken;
+use crate::config;
+use crate::history::History;
+use crate::jobs::{Cursor, Job, Jobs};
+use crate::metrics::Metrics;
+use crate::submit::spawn_request;
+use crate::util::{Arguments, Envelope, ErrorKind, ReleaseNotes, ServerMessage, Usage};
+
+#[derive(Serialize)]
+struct GenerateResponse {
+    release_notes: ReleaseNotes,
+    usage: Usage,
+    warnings: Vec<String>
+}
+
+fn error_response(kind: ErrorKind, message: String) -> Response {
+    let status = match kind {
+        ErrorKind::InvalidRequest => StatusCode::BAD_REQUEST,
+        ErrorKind::RepoNotAllowed => StatusCode::FORBIDDEN,
+        ErrorKind::RepoTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        ErrorKind::CloneTimedOut => StatusCode::GATEWAY_TIMEOUT,
+        ErrorKind::Git => StatusCode::UNPROCESSABLE_ENTITY,
+        ErrorKind::Llm => StatusCode::BAD_GATEWAY,
+        // client closed request, as nginx calls it
+        ErrorKind::Cancelled => StatusCode::from_u16(499).expect("499 is a valid status code."),
+        ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    return (status, Json(json!({ "kind": kind, "message": message }))).into_response();
+}
+
+// POST /api/v1/release-notes, responds once the notes are finished
+pub async fn generate(arguments: Result<Json<Arguments>, JsonRejection>) -> Response {
+    let Json(arguments) = match arguments {
+        Ok(arguments) => arguments,
+        Err(rejection) => return error_response(ErrorKind::InvalidRequest, rejection.body_text())
+    };
+
+    // cancelled by spawn_request when the client disconnects and the receiver is dropped
+    let mut recv = spawn_request(arguments, CancelToken::default());
+    let mut release_notes = None;
+    let mut warnings = vec![];
+    while let Some(message) = recv.recv().await {
+        match message {
+            ServerMessage::Warning { message } => warnings.push(message),
+            ServerMessage::Notes { release_notes: notes } => release_notes = Some(notes),
+            ServerMessage::Error { kind, message } => return error_response(kind, message),
+            ServerMessage::Done { usage } => {
+                let Some(release_notes) = release_notes else {
+                    break;
+                };
+                return Json(GenerateResponse { release_notes, usage, warnings }).into_response();
+            },
+            ServerMessage::Job { .. }
+                | ServerMessage::Progress { .. }
+                | ServerMessage::Transfer { .. }
+                | ServerMessage::Cleanup { .. }
+                | ServerMessage::CommitsFound { .. }
+                | ServerMessage::TicketLinks { .. }
+                | ServerMessage::Token { .. } => {}
+        }
+    }
+
+    return error_response(ErrorKind::Internal, "The request ended without release notes.".to_string());
+}
+
+fn job_events(job: Arc<Job>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let job_message = ServerMessage::Job { id: job.id.clone() };
+    // the attachment is dropped with the stream when the client disconnects
+    let attachment = job.attach();
+    let messages = stream::once(async move { job_message })
+        .chain(stream::unfold((job, attachment, Cursor::default()), |(job, attachment, mut cursor)| async move {
+            let message = job.message(&mut cursor).await?;
+            return Some((message, (job, attachment, cursor)));
+        }));
+
+    let events = messages.map(|message| {
+        let data = serde_json::to_value(Envelope::new(message))
+            .expect("Serializing a server message should always succeed.");
+        let event = Event::default()
+            .event(data["type"].as_str().unwrap_or("message"))
+            .json_data(&data)
+            .expect("Serializing a server message should always succeed.");
+        return Ok(event);
+    });
+
+    return Sse::new(events).keep_alive(KeepAlive::default());
+}
+
+// POST /api/v1/release-notes/stream, every event carries the same Envelope as a frame on /submit and is named
+// after its type, so SSE clients can listen for e.g. "notes" only
+pub async fn generate_stream(
+    arguments: Result<Json<Arguments>, JsonRejection>
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    let Json(arguments) = arguments
+        .map_err(|rejection| error_response(ErrorKind::InvalidRequest, rejection.body_text()))?;
+
+    return Ok(job_events(Jobs::global().start(arguments)));
+}
+
+// GET /api/v1/jobs/:id/stream, replays the job started by either stream and follows it
+pub async fn attach_stream(Path(id): Path<String>) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    let job = Jobs::global()
+        .get(&id)
+        .ok_or_else(|| error_response(ErrorKind::InvalidRequest, format!("Job {id} doesn't exist or has expired.")))?;
+
+    return Ok(job_events(job));
+}
+
+// DELETE /api/v1/jobs/:id, cancels the job for every client following it
+pub async fn cancel_job(Path(id): Path<String>) -> Response {
+    let Some(job) = Jobs::global().get(&id) else {
+        return error_response(ErrorKind::InvalidRequest, format!("Job {id} doesn't exist or has expired."));
+    };
+
+    job.cancel("cancelled through the API");
+    return StatusCode::NO_CONTENT.into_response();
+}
+
+fn admin_error(status: StatusCode, message: String) -> Response {
+    return (status, Json(json!({ "message": message }))).into_response();
+}
+
+// compares every byte, so the time taken doesn't tell how much of a guessed token was right
+fn tokens_match(provided: &str, token: &str) -> bool {
+    return provided.len() == token.len()
+        && provided.bytes().zip(token.bytes()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0;
+}
+
+// whether provided is the admin.token, nothing is without one
+pub fn is_admin_token(provided: &str) -> bool {
+    return config::get().admin.token
+        .as_ref()
+        .is_some_and(|token| tokens_match(provided.trim(), token.trim()));
+}
+
+// the admin endpoints take the admin.token as a bearer token and don't exist without one,
+// returns the response to send instead if the request isn't authorized
+fn unauthorized(headers: &HeaderMap) -> Option<Response> {
+    if config::get().admin.token.is_none() {
+        return Some(StatusCode::NOT_FOUND.into_response());
+    }
+
+    let provided = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !provided.is_some_and(is_admin_token) {
+        return Some(admin_error(StatusCode::UNAUTHORIZED, "A valid admin token is required.".to_string()));
+    }
+
+    return None;
+}
+
+// GET /api/v1/admin/metrics, counters since the server started
+pub async fn metrics(headers: HeaderMap) -> Response {
+    if let Some(response) = unauthorized(&headers) {
+        return response;
+    }
+
+    return Json(Metrics::global()).into_response();
+}
+
+// GET /api/v1/admin/repos, the cached repos, most recently used first
+pub async fn list_repos(headers: HeaderMap) -> Response {
+    if let Some(response) = unauthorized(&headers) {
+        return response;
+    }
+
+    return Json(RepoCache::global().list()).into_response();
+}
+
+// DELETE /api/v1/admin/repos/:digest
+pub async fn purge_repo(headers: HeaderMap, Path(digest): Path<String>) -> Response {
+    if let Some(response) = unauthorized(&headers) {
+        return response;
+    }
+
+    // waits for a fetch of the repo and removes its directory, so it mustn't run on the async workers
+    let removed = {
+        let digest = digest.clone();
+        tokio::task::spawn_blocking(move || RepoCache::global().remove(&digest)).await
+    };
+    match removed.unwrap_or_else(|error| Err(error.into())) {
+        Ok(true) => return StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => return admin_error(StatusCode::NOT_FOUND, format!("Repository {digest} isn't cached.")),
+        Err(error) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, error.to_string())
+    }
+}
+
+// DELETE /api/v1/admin/repos, empties the whole cache
+pub async fn purge_repos(headers: HeaderMap) -> Response {
+    if let Some(response) = unauthorized(&headers) {
+        return response;
+    }
+
+    let cleared = tokio::task::spawn_blocking(|| RepoCache::global().clear()).await;
+    match cleared.unwrap_or_else(|error| Err(error.into())) {
+        Ok(removed) => return Json(json!({ "removed": removed })).into_response(),
+        Err(error) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, error.to_string())
+    }
+}
+
+// DELETE /api/v1/admin/runs/:id, removes a run from the history
+pub async fn delete_run(headers: HeaderMap, Path(id): Path<i64>) -> Response {
+    if let Some(response) = unauthorized(&headers) {
+        return response;
+    }
+
+    match History::blocking(move |history| history.delete(id)).await {
+        Ok(true) => return StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => return admin_error(StatusCode::NOT_FOUND, format!("Run {id} doesn't exist.")),
+        Err(error) => return admin_error(StatusCode::INTERNAL_SERVER_ERROR, error.to_string())
+    }
+}
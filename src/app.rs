@@ -4,7 +4,8 @@ use leptos_meta::*;
 use leptos_router::*;
 
 use crate::form::Form;
-use crate::util::{Arguments, Ticket};
+use crate::history_page::HistoryPage;
+use crate::util::{Arguments, Contributor, ReleaseNote, ReleaseNotes, Ticket};
 
 #[component]
 pub fn RootApp() -> impl IntoView {
@@ -28,10 +29,15 @@ pub fn RootApp() -> impl IntoView {
             }
             .into_view()
         }>
+            <nav class="px-[2vw] pt-[1vh] flex gap-4 underline">
+                <A href="/">"Generate"</A>
+                <A href="/history">"History"</A>
+            </nav>
             <main class="p-[2vw] text-[1.1em]">
                 <Routes>
                     <Route path="" view=HomePage />
                     <Route path="/test" view=TestPage />
+                    <Route path="/history" view=HistoryPage />
                 </Routes>
             </main>
         </Router>
@@ -40,9 +46,11 @@ pub fn RootApp() -> impl IntoView {
 
 #[component]
 fn HomePage() -> impl IntoView {
-    let mut arguments = Arguments::default();
-    arguments.release_date = Local::now().date_naive();
-    arguments.tickets.push(Ticket::default());
+    let arguments = Arguments {
+        release_date: Local::now().date_naive(),
+        tickets: vec![Ticket::default()],
+        ..Arguments::default()
+    };
 
     view! {
         <App default_arguments={arguments} />
@@ -61,27 +69,97 @@ fn TestPage() -> impl IntoView {
 
 #[component]
 fn App(default_arguments: Arguments) -> impl IntoView {
-    let (release_notes, set_release_notes) = create_signal("".to_string());
+    let (release_notes, set_release_notes) = create_signal(None::<ReleaseNotes>);
+    let (preview, set_preview) = create_signal(String::new());
 
     view! {
         <h1 class="text-[1.5em]">"ReleaseNotes.ai"</h1>
         <div class="grid grid-cols-[50vw_40vw]">
-            <Form default_arguments set_release_notes />
-            <ReleaseNotes release_notes />
+            <Form default_arguments set_release_notes set_preview />
+            <ReleaseNotesView release_notes preview />
         </div>
     }
 }
 
 #[component]
-fn ReleaseNotes(release_notes: ReadSignal<String>) -> impl IntoView {
+fn ReleaseNotesSection(heading: &'static str, notes: Vec<ReleaseNote>) -> impl IntoView {
+    view! {
+        <h2 class="mt-[1em] font-bold">{heading}</h2>
+        <p style:display=(!notes.is_empty()).then_some("None")>"None."</p>
+        <ul class="pl-[1.5em] list-disc">
+            {notes
+                .into_iter()
+                .map(|note| view! {
+                    <li>
+                        <span class="font-semibold">{note.title}": "</span>
+                        {note.description}
+                        <span
+                            class="block text-[0.8em] text-gray-600"
+                            style:display=note.references.is_empty().then_some("None")
+                        >{format!("Based on: {}", note.references.join("; "))}</span>
+                    </li>
+                })
+                .collect_view()}
+        </ul>
+    }
+}
+
+// only shown when the contributors were credited, first-time contributors stand out
+#[component]
+fn ContributorsSection(contributors: Vec<Contributor>) -> impl IntoView {
+    view! {
+        <div style:display=contributors.is_empty().then_some("None")>
+            <h2 class="mt-[1em] font-bold">"Contributors"</h2>
+            <p>
+                {contributors
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, contributor)| view! {
+                        {(i > 0).then_some(", ")}
+                        <span class:font-semibold=contributor.first_time>{contributor.name}</span>
+                        {contributor.first_time.then_some(" (first contribution)")}
+                    })
+                    .collect_view()}
+            </p>
+        </div>
+    }
+}
+
+// the raw output of the model is shown while it's generated, until the parsed notes replace it
+#[component]
+pub fn ReleaseNotesView(
+    #[prop(into)] release_notes: Signal<Option<ReleaseNotes>>,
+    #[prop(optional, into)] preview: MaybeSignal<String>
+) -> impl IntoView {
+    let preview = Signal::derive(move || preview.get());
+
     view! {
         <div
-            style:display=move || release_notes().is_empty().then(|| "None")
+            style:display=move || (release_notes().is_none() && preview.with(String::is_empty)).then_some("None")
         >
             <h1 class="text-[1.2em] underline">"Release Notes"</h1>
-            <p class="my-[5vh] p-[1vw] w-[35vw] text-[0.9rem] border-2 border-black">{
-                release_notes
-            }</p>
+            <div class="my-[5vh] p-[1vw] w-[35vw] text-[0.9rem] border-2 border-black">{
+                move || {
+                    let Some(release_notes) = release_notes() else {
+                        return view! {
+                            <pre class="whitespace-pre-wrap text-gray-600">{move || preview.get()}</pre>
+                        }.into_view();
+                    };
+
+                    let ReleaseNotes {
+                        product_name, version, release_date, overview, features, enhancements, fixes, contributors
+                    } = release_notes;
+                    view! {
+                        <h2 class="font-bold">{format!("{product_name} Release Notes - {version} - {release_date}")}</h2>
+                        <h2 class="mt-[1em] font-bold">"Overview"</h2>
+                        <p>{overview}</p>
+                        <ReleaseNotesSection heading="New Features" notes=features />
+                        <ReleaseNotesSection heading="Enhancements" notes=enhancements />
+                        <ReleaseNotesSection heading="Fixes" notes=fixes />
+                        <ContributorsSection contributors />
+                    }.into_view()
+                }
+            }</div>
         </div>
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,243 @@
+use anyhow::{anyhow, Result};
+use chrono::{Local, NaiveDate};
+use clap::{Parser, Subcommand, ValueEnum};
+use git2::Repository;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use releasenotes::cache::RepoGuard;
+use releasenotes::cancel::CancelToken;
+use releasenotes::commits::Classifier;
+use releasenotes::config;
+use releasenotes::contributors::BotFilter;
+use releasenotes::git::{clean_up_history, fetch_or_clone, read_commits, read_contributors, read_highlights, PathFilter};
+use releasenotes::submit::generate_release_notes;
+use releasenotes::util::{Arguments, ServerMessage, TargetAudience, Ticket};
+
+#[derive(Parser)]
+#[command(name = "releasenotes-cli", about = "Generates release notes from the git log between two tags")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate release notes for the commits between two tags
+    Generate(GenerateArgs)
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Audience {
+    NonTechnical,
+    ProjectManager,
+    Technical
+}
+
+impl From<Audience> for TargetAudience {
+    fn from(audience: Audience) -> Self {
+        match audience {
+            Audience::NonTechnical => TargetAudience::NonTechnical,
+            Audience::ProjectManager => TargetAudience::ProjectManager,
+            Audience::Technical => TargetAudience::Technical
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Text,
+    Json
+}
+
+#[derive(clap::Args)]
+struct GenerateArgs {
+    /// Path to a local repository, or a link to a remote one
+    #[arg(long, default_value = ".")]
+    repo: String,
+    /// Previous release tag
+    #[arg(long)]
+    from: String,
+    /// Release tag
+    #[arg(long)]
+    to: String,
+    #[arg(long, value_enum, default_value = "project-manager")]
+    audience: Audience,
+    /// JSON file with an array of tickets, each with a summary, a description and optionally an id and a url
+    #[arg(long)]
+    tickets: Option<PathBuf>,
+    /// Only keep commits changing paths that match this pathspec, e.g. 'services/billing/**'. Can be repeated
+    #[arg(long)]
+    include: Vec<String>,
+    /// Leave out changes to paths that match this pathspec. Can be repeated
+    #[arg(long)]
+    exclude: Vec<String>,
+    /// Credit the authors, co-authors and committers of the changes in a Contributors section
+    #[arg(long)]
+    contributors: bool,
+    /// Pass the git notes of the commits to the model along with the message of the release tag
+    #[arg(long)]
+    notes: bool,
+    /// Product name, defaults to the name of the repository
+    #[arg(long)]
+    product: Option<String>,
+    /// Release date, defaults to today
+    #[arg(long)]
+    date: Option<NaiveDate>,
+    #[arg(long, value_enum, default_value = "text")]
+    format: Format,
+    /// Write the notes to this file instead of stdout
+    #[arg(long)]
+    out: Option<PathBuf>
+}
+
+// every failure maps to its own exit code so pipelines can tell them apart, 2 is used by clap for usage errors
+enum Failure {
+    Input(anyhow::Error),
+    Git(anyhow::Error),
+    Llm(anyhow::Error),
+    Output(anyhow::Error)
+}
+
+impl Failure {
+    fn exit_code(&self) -> ExitCode {
+        match self {
+            Failure::Input(_) => ExitCode::from(3),
+            Failure::Git(_) => ExitCode::from(4),
+            Failure::Llm(_) => ExitCode::from(5),
+            Failure::Output(_) => ExitCode::from(6)
+        }
+    }
+
+    fn error(&self) -> &anyhow::Error {
+        match self {
+            Failure::Input(error) | Failure::Git(error) | Failure::Llm(error) | Failure::Output(error) => error
+        }
+    }
+}
+
+fn read_tickets(path: Option<&Path>) -> Result<Vec<Ticket>> {
+    let Some(path) = path else {
+        return Ok(vec![]);
+    };
+
+    let contents = std::fs::read_to_string(path)
+        .map_err(|error| anyhow!("Unable to read {}: {error}", path.display()))?;
+    return serde_json::from_str(&contents)
+        .map_err(|error| anyhow!("Invalid tickets file {}: {error}", path.display()));
+}
+
+// local repositories are read in place, anything else goes through the same cache as the server
+// the guard keeps a cached repo from being removed while it's read, local repos aren't cached
+fn open_repo(repo: &str) -> Result<(Repository, Option<RepoGuard<'static>>)> {
+    if Path::new(repo).exists() {
+        return Ok((Repository::discover(repo)?, None));
+    }
+
+    let (repo, guard) = fetch_or_clone(repo.to_string(), &config::get().credentials, |_| {}, &CancelToken::default())?;
+    return Ok((repo, Some(guard)));
+}
+
+fn product_name(repo: &Repository, repo_link: &str) -> String {
+    let path = match repo.workdir() {
+        Some(workdir) => workdir.to_path_buf(),
+        None => PathBuf::from(repo_link)
+    };
+
+    return path
+        .file_name()
+        .map(|name| name.to_string_lossy().trim_end_matches(".git").to_string())
+        .unwrap_or_default();
+}
+
+async fn generate(args: GenerateArgs) -> Result<(), Failure> {
+    let tickets = read_tickets(args.tickets.as_deref()).map_err(Failure::Input)?;
+    let config = config::init().map_err(Failure::Input)?;
+
+    let filter = PathFilter::new(&args.include, &args.exclude).map_err(Failure::Input)?;
+
+    let (repo, _guard) = open_repo(&args.repo).map_err(Failure::Git)?;
+    let commits = read_commits(&repo, &args.to, &args.from, &filter, &config.credentials, &CancelToken::default())
+        .map_err(Failure::Git)?;
+    let (commits, removed) = clean_up_history(&repo, commits, &args.to, &args.from, &config.credentials, &CancelToken::default())
+        .map_err(Failure::Git)?;
+    for commit in removed {
+        eprintln!("Left out {} {}: {}.", &commit.id[..7], commit.subject, commit.describe());
+    }
+    let contributors = match args.contributors {
+        true => {
+            let bots = BotFilter::new(&config.contributors.bot_patterns).map_err(Failure::Input)?;
+            read_contributors(&repo, &commits, &args.to, &args.from, &bots, &config.credentials, &CancelToken::default())
+                .map_err(Failure::Git)?
+        },
+        false => vec![]
+    };
+    let highlights = read_highlights(&repo, &commits, &args.to, args.notes, &config.credentials, &CancelToken::default())
+        .map_err(Failure::Git)?;
+    let classifier = Classifier::new(&config.commits.fallback_patterns).map_err(Failure::Input)?;
+    let commits = classifier.categorize(&commits);
+
+    let arguments = Arguments {
+        product_name: args.product.unwrap_or_else(|| product_name(&repo, &args.repo)),
+        repo_link: args.repo,
+        release_tag: args.to,
+        prev_release_tag: args.from,
+        release_date: args.date.unwrap_or_else(|| Local::now().date_naive()),
+        target_audience: args.audience.into(),
+        tickets,
+        include_paths: args.include,
+        exclude_paths: args.exclude,
+        credit_contributors: args.contributors,
+        include_notes: args.notes
+    };
+
+    let provider = config.llm.provider().map_err(Failure::Llm)?;
+    let on_event = |message: ServerMessage| {
+        match message {
+            ServerMessage::Progress { stage, .. } => eprintln!("{stage}..."),
+            ServerMessage::Warning { message } => eprintln!("Warning: {message}"),
+            ServerMessage::TicketLinks { report } => {
+                if !report.unlinked_commits.is_empty() {
+                    eprintln!("{} commits reference none of the tickets.", report.unlinked_commits.len());
+                }
+                for ticket in report.unlinked_tickets {
+                    eprintln!("No commit references the ticket {ticket}.");
+                }
+            },
+            _ => {}
+        }
+    };
+    let mut release_notes = generate_release_notes(&config.llm, provider.as_ref(), &arguments, &highlights, commits, on_event)
+        .await
+        .map_err(Failure::Llm)?;
+    release_notes.contributors = contributors;
+
+    let output = match args.format {
+        Format::Text => release_notes.render(),
+        Format::Json => serde_json::to_string_pretty(&release_notes).map_err(|error| Failure::Output(error.into()))?
+    };
+    match args.out {
+        Some(path) => std::fs::write(&path, output)
+            .map_err(|error| Failure::Output(anyhow!("Unable to write {}: {error}", path.display())))?,
+        None => println!("{output}")
+    }
+
+    return Ok(());
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let Cli { command } = Cli::parse();
+
+    let result = match command {
+        Command::Generate(args) => generate(args).await
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            eprintln!("Error: {}", failure.error());
+            failure.exit_code()
+        }
+    }
+}
@@ -0,0 +1,393 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, OnceLock, PoisonError, TryLockError};
+use std::time::Instant;
+
+use crate::config;
+use crate::sandbox::RepoUrl;
+
+const INDEX_FILE: &str = "index.json";
+const CLONE_PREFIX: &str = ".clone-";
+
+// held while a repo is fetched or removed, with when it was last fetched
+pub type RepoLock = Arc<Mutex<Option<Instant>>>;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CachedRepo {
+    pub digest: String,             // the name of the repo's directory
+    pub key: String,                // see RepoUrl::cache_key
+    pub last_used: DateTime<Utc>,
+    pub size_bytes: u64
+}
+
+// SHA-1 of the normalized link, unlike DefaultHasher it stays the same across Rust releases
+pub fn digest(key: &str) -> String {
+    return format!("{:x}", Sha1::digest(key.as_bytes()));
+}
+
+pub(crate) fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+
+    return entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => dir_size(&entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0
+        })
+        .sum();
+}
+
+// a directory that is already gone counts as removed
+fn remove_dir(path: &Path) -> Result<()> {
+    match std::fs::remove_dir_all(path) {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+            return Err(anyhow!("Unable to remove the cached repository {}: {error}", path.display()));
+        },
+        _ => return Ok(())
+    }
+}
+
+fn origin_key(path: &Path) -> Option<String> {
+    let repo = Repository::open(path).ok()?;
+    let remote = repo.find_remote("origin").ok()?;
+    return RepoUrl::parse(remote.url()?).ok().map(|url| url.cache_key());
+}
+
+// held while a cached repo is read, keeps it from being fetched into, evicted or removed until dropped
+pub struct RepoGuard<'a> {
+    cache: &'a RepoCache,
+    digest: String
+}
+
+impl Drop for RepoGuard<'_> {
+    fn drop(&mut self) {
+        let mut readers = self.cache.readers.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(count) = readers.get_mut(&self.digest) {
+            *count -= 1;
+            if *count == 0 {
+                readers.remove(&self.digest);
+            }
+        }
+        self.cache.released.notify_all();
+    }
+}
+
+// every cached repo is listed in <repos.path>/index.json with its last use and size, so the least recently used
+// ones can be removed without walking the whole cache
+pub struct RepoCache {
+    path: PathBuf,
+    max_size_mb: Option<u64>,
+    repos: Mutex<Vec<CachedRepo>>,
+    locks: Mutex<HashMap<String, RepoLock>>,
+    readers: Mutex<HashMap<String, usize>>,
+    released: Condvar
+}
+
+impl RepoCache {
+    pub fn global() -> &'static RepoCache {
+        static CACHE: OnceLock<RepoCache> = OnceLock::new();
+        let config = config::get();
+        return CACHE.get_or_init(|| RepoCache::open(&config.repos.path, config.repos.max_size_mb));
+    }
+
+    // a missing or corrupt index is rebuilt from the repos on disk
+    pub fn open(path: &Path, max_size_mb: Option<u64>) -> RepoCache {
+        let repos = std::fs::read_to_string(path.join(INDEX_FILE))
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        let cache = RepoCache {
+            path: path.to_path_buf(),
+            max_size_mb,
+            repos: Mutex::new(repos),
+            locks: Mutex::default(),
+            readers: Mutex::default(),
+            released: Condvar::new()
+        };
+        cache.adopt_unindexed();
+
+        return cache;
+    }
+
+    // directories missing from the index, such as the ones named by older versions, are renamed to the digest of
+    // their origin, so an upgrade doesn't clone everything again. clones that were interrupted are removed
+    fn adopt_unindexed(&self) {
+        let Ok(entries) = std::fs::read_dir(&self.path) else {
+            return;
+        };
+
+        let mut repos = self.repos.lock().unwrap();
+        repos.retain(|repo| self.path.join(&repo.digest).is_dir());
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with(CLONE_PREFIX) {
+                let _ = std::fs::remove_dir_all(entry.path());
+                continue;
+            }
+            if !entry.path().is_dir() || name.starts_with('.') || repos.iter().any(|repo| repo.digest == name) {
+                continue;
+            }
+            let Some(key) = origin_key(&entry.path()) else {
+                continue;
+            };
+
+            let digest = digest(&key);
+            if repos.iter().any(|repo| repo.digest == digest) {
+                // the same repo under another link
+                let _ = std::fs::remove_dir_all(entry.path());
+                continue;
+            }
+            if digest != name && std::fs::rename(entry.path(), self.path.join(&digest)).is_err() {
+                continue;
+            }
+
+            let last_used = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .map(DateTime::from)
+                .unwrap_or_else(|_| Utc::now());
+            let size_bytes = dir_size(&self.path.join(&digest));
+            repos.push(CachedRepo { digest, key, last_used, size_bytes });
+        }
+
+        if let Err(error) = self.save(&repos) {
+            leptos::logging::warn!("Unable to save the repository cache index: {error}");
+        }
+    }
+
+    // written to a temporary file first, so a crash can't leave half an index behind
+    fn save(&self, repos: &[CachedRepo]) -> Result<()> {
+        std::fs::create_dir_all(&self.path)?;
+        let temp_path = self.path.join(format!("{INDEX_FILE}.tmp"));
+        std::fs::write(&temp_path, serde_json::to_string_pretty(repos)?)?;
+        std::fs::rename(&temp_path, self.path.join(INDEX_FILE))?;
+
+        return Ok(());
+    }
+
+    pub fn dir(&self, url: &RepoUrl) -> PathBuf {
+        return self.path.join(digest(&url.cache_key()));
+    }
+
+    // repos are cloned here and renamed to dir once complete
+    pub fn clone_dir(&self, url: &RepoUrl) -> PathBuf {
+        return self.path.join(format!("{CLONE_PREFIX}{}", digest(&url.cache_key())));
+    }
+
+    fn digest_lock(&self, digest: &str) -> RepoLock {
+        return self.locks.lock().unwrap().entry(digest.to_string()).or_default().clone();
+    }
+
+    pub fn lock(&self, url: &RepoUrl) -> RepoLock {
+        return self.digest_lock(&digest(&url.cache_key()));
+    }
+
+    // taken while holding the repo's lock, so it can't be fetched into or removed in between
+    pub fn read(&self, url: &RepoUrl) -> RepoGuard<'_> {
+        let digest = digest(&url.cache_key());
+        *self.readers.lock().unwrap().entry(digest.clone()).or_default() += 1;
+        return RepoGuard { cache: self, digest };
+    }
+
+    // called while holding the repo's lock, so no new readers come in while waiting
+    pub fn wait_for_readers(&self, url: &RepoUrl) {
+        self.wait_for_digest_readers(&digest(&url.cache_key()));
+    }
+
+    fn wait_for_digest_readers(&self, digest: &str) {
+        let readers = self.readers.lock().unwrap();
+        let _readers = self.released
+            .wait_while(readers, |readers| readers.contains_key(digest))
+            .unwrap();
+    }
+
+    // removes the repo unless it's being fetched or read. its lock is held from the check to the removal, and
+    // readers only come in while holding it
+    fn evict(&self, digest: &str) -> Result<bool> {
+        let lock = self.digest_lock(digest);
+        let mut fetched_at = match lock.try_lock() {
+            Ok(fetched_at) => fetched_at,
+            Err(TryLockError::Poisoned(error)) => error.into_inner(),
+            Err(TryLockError::WouldBlock) => return Ok(false)
+        };
+        if self.readers.lock().unwrap().contains_key(digest) {
+            return Ok(false);
+        }
+
+        remove_dir(&self.path.join(digest))?;
+        *fetched_at = None;
+        let mut repos = self.repos.lock().unwrap();
+        repos.retain(|repo| repo.digest != digest);
+        self.save(&repos)?;
+
+        return Ok(true);
+    }
+
+    // marks the repo as just used and removes the least recently used others until the cache fits into
+    // repos.max_size_mb, skipping the ones being fetched or read
+    pub fn record_use(&self, url: &RepoUrl) -> Result<()> {
+        let key = url.cache_key();
+        let digest = digest(&key);
+        let size_bytes = dir_size(&self.path.join(&digest));
+
+        let mut repos = {
+            let mut repos = self.repos.lock().unwrap();
+            repos.retain(|repo| repo.digest != digest);
+            repos.push(CachedRepo { digest: digest.clone(), key, last_used: Utc::now(), size_bytes });
+            self.save(&repos)?;
+            repos.clone()
+        };
+
+        let Some(max_size_mb) = self.max_size_mb else {
+            return Ok(());
+        };
+        // directories are removed outside of the index's lock, so using or listing the cache doesn't wait for them
+        let mut size: u64 = repos.iter().map(|repo| repo.size_bytes).sum();
+        repos.retain(|repo| repo.digest != digest);
+        repos.sort_by_key(|repo| repo.last_used);
+        for repo in repos {
+            if size <= max_size_mb * 1024 * 1024 {
+                break;
+            }
+            if self.evict(&repo.digest)? {
+                size = size.saturating_sub(repo.size_bytes);
+            }
+        }
+
+        return Ok(());
+    }
+
+    // most recently used first
+    pub fn list(&self) -> Vec<CachedRepo> {
+        let mut repos = self.repos.lock().unwrap().clone();
+        repos.sort_by_key(|repo| std::cmp::Reverse(repo.last_used));
+        return repos;
+    }
+
+    // returns whether the repo was cached, only digests from the index are ever joined to the path.
+    // waits for a fetch of the repo and the requests reading it to finish first
+    pub fn remove(&self, digest: &str) -> Result<bool> {
+        let lock = self.digest_lock(digest);
+        let mut fetched_at = lock.lock().unwrap_or_else(PoisonError::into_inner);
+        self.wait_for_digest_readers(digest);
+        if !self.repos.lock().unwrap().iter().any(|repo| repo.digest == digest) {
+            return Ok(false);
+        }
+
+        remove_dir(&self.path.join(digest))?;
+        *fetched_at = None;
+        let mut repos = self.repos.lock().unwrap();
+        repos.retain(|repo| repo.digest != digest);
+        self.save(&repos)?;
+
+        return Ok(true);
+    }
+
+    // returns how many repos were removed
+    pub fn clear(&self) -> Result<usize> {
+        let digests = self.repos
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|repo| repo.digest.clone())
+            .collect::<Vec<_>>();
+
+        let mut count = 0;
+        for digest in digests {
+            if self.remove(&digest)? {
+                count += 1;
+            }
+        }
+
+        return Ok(count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn cached(cache: &RepoCache, link: &str, size: usize) -> RepoUrl {
+        let url = RepoUrl::parse(link).unwrap();
+        std::fs::create_dir_all(cache.dir(&url)).unwrap();
+        std::fs::write(cache.dir(&url).join("pack"), vec![0; size]).unwrap();
+        cache.record_use(&url).unwrap();
+        return url;
+    }
+
+    #[test]
+    fn eviction_skips_repos_being_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = RepoCache::open(dir.path(), Some(1));
+        let read = cached(&cache, "https://example.com/owner/read", 700 * 1024);
+        let guard = cache.read(&read);
+        let unread = cached(&cache, "https://example.com/owner/unread", 700 * 1024);
+
+        // the newest repo is kept and the one being read is skipped, so the cache stays over its size
+        assert!(cache.dir(&read).exists());
+        assert!(cache.dir(&unread).exists());
+
+        drop(guard);
+        let newest = cached(&cache, "https://example.com/owner/newest", 100);
+        assert!(!cache.dir(&read).exists());
+        assert!(cache.dir(&newest).exists());
+    }
+
+    #[test]
+    fn eviction_checks_again_before_removing() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = RepoCache::open(dir.path(), Some(1));
+        let fetched = cached(&cache, "https://example.com/owner/fetched", 100);
+        let read = cached(&cache, "https://example.com/owner/read", 100);
+        let selected = cache.list();
+
+        // a fetch and a reader come in after the repos were selected for removal
+        let lock = cache.lock(&fetched);
+        let fetching = lock.lock().unwrap();
+        let guard = cache.read(&read);
+        for repo in &selected {
+            assert!(!cache.evict(&repo.digest).unwrap(), "{}", repo.key);
+        }
+        assert!(cache.dir(&fetched).exists());
+        assert!(cache.dir(&read).exists());
+        assert_eq!(cache.list().len(), 2);
+
+        drop(fetching);
+        drop(guard);
+        for repo in &selected {
+            assert!(cache.evict(&repo.digest).unwrap(), "{}", repo.key);
+        }
+        assert!(!cache.dir(&fetched).exists());
+        assert!(!cache.dir(&read).exists());
+        assert!(cache.list().is_empty());
+    }
+
+    #[test]
+    fn remove_waits_for_readers() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = RepoCache::open(dir.path(), None);
+        let url = cached(&cache, "https://example.com/owner/repo", 100);
+        let digest = digest(&url.cache_key());
+
+        let guard = cache.read(&url);
+        std::thread::scope(|scope| {
+            let removal = scope.spawn(|| cache.remove(&digest).unwrap());
+            std::thread::sleep(Duration::from_millis(100));
+            assert!(!removal.is_finished());
+            assert!(cache.dir(&url).exists());
+
+            drop(guard);
+            assert!(removal.join().unwrap());
+        });
+        assert!(!cache.dir(&url).exists());
+    }
+}
@@ -0,0 +1,52 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+#[derive(thiserror::Error, Debug)]
+#[error("The generation was cancelled.")]
+pub struct Cancelled;
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    reason: Mutex<Option<String>>,
+    notify: Notify
+}
+
+// shared by a generation and everything it starts, async code awaits it while the blocking git thread polls it
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<Inner>);
+
+impl CancelToken {
+    // only the first reason is kept
+    pub fn cancel(&self, reason: &str) {
+        let mut stored = self.0.reason.lock().unwrap();
+        if stored.is_none() {
+            *stored = Some(reason.to_string());
+        }
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        self.0.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        return self.0.cancelled.load(Ordering::SeqCst);
+    }
+
+    pub fn reason(&self) -> Option<String> {
+        return self.0.reason.lock().unwrap().clone();
+    }
+
+    pub async fn cancelled(&self) {
+        loop {
+            // registered before looking at the flag so a cancel in between isn't missed
+            let notified = self.0.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
@@ -0,0 +1,440 @@
+use anyhow::{anyhow, Result};
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+use crate::git::{CommitInfo, DiffStat};
+
+// top-level paths listed per commit and for the whole release, the rest are only counted
+const MAX_PROMPT_PATHS: usize = 5;
+const MAX_PROMPT_COMPONENTS: usize = 10;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Feature,
+    Enhancement,
+    Fix,
+    Other
+}
+
+#[derive(Clone, Debug)]
+pub struct ClassifiedCommit {
+    pub id: String,
+    pub diffstat: DiffStat,
+    pub category: Category,
+    pub commit_type: Option<String>,        // None if the header doesn't follow Conventional Commits
+    pub scope: Option<String>,
+    pub breaking_change: Option<String>,    // what breaks, taken from the footer if there is one
+    pub description: String,
+    pub body: String
+}
+
+// the size of a commit, so the model can tell a typo fix from a rewrite, e.g. "3 files, +120 -4 in src/, docs/"
+fn diffstat_to_prompt(diffstat: &DiffStat) -> Option<String> {
+    let DiffStat { files_changed, insertions, deletions, paths } = diffstat;
+    if *files_changed == 0 {
+        return None;
+    }
+
+    let files = match files_changed {
+        1 => "1 file".to_string(),
+        _ => format!("{files_changed} files")
+    };
+    let mut listed = paths
+        .iter()
+        .take(MAX_PROMPT_PATHS)
+        .map(|stat| stat.path.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if paths.len() > MAX_PROMPT_PATHS {
+        listed += &format!(" and {} more", paths.len() - MAX_PROMPT_PATHS);
+    }
+
+    return Some(format!("{files}, +{insertions} -{deletions} in {listed}"));
+}
+
+// how much of the release went into a top-level path of the repo
+#[derive(Clone, Debug, PartialEq)]
+pub struct Component {
+    pub path: String,
+    pub commits: usize,
+    pub insertions: usize,
+    pub deletions: usize
+}
+
+pub fn components_to_prompt(components: &[Component]) -> Option<String> {
+    if components.is_empty() {
+        return None;
+    }
+
+    let mut lines = components
+        .iter()
+        .take(MAX_PROMPT_COMPONENTS)
+        .map(|Component { path, commits, insertions, deletions }| format!("- {path}: +{insertions} -{deletions} in {commits} commits"))
+        .collect::<Vec<_>>();
+    if components.len() > MAX_PROMPT_COMPONENTS {
+        lines.push(format!("- {} other paths", components.len() - MAX_PROMPT_COMPONENTS));
+    }
+
+    return Some(format!("Lines changed per top-level path, largest first:\n{}", lines.join("\n")));
+}
+
+impl ClassifiedCommit {
+    pub fn to_prompt(&self) -> String {
+        let mut line = match &self.scope {
+            Some(scope) => format!("- ({scope}) {}", self.description),
+            None => format!("- {}", self.description)
+        };
+        if let Some(diffstat) = diffstat_to_prompt(&self.diffstat) {
+            line += &format!(" [{diffstat}]");
+        }
+
+        if !self.body.is_empty() {
+            line += &format!("\n  {}", self.body.replace('\n', "\n  "));
+        }
+
+        return line;
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CategorizedCommits {
+    pub features: Vec<ClassifiedCommit>,
+    pub enhancements: Vec<ClassifiedCommit>,
+    pub fixes: Vec<ClassifiedCommit>,
+    pub other: Vec<ClassifiedCommit>
+}
+
+impl CategorizedCommits {
+    pub fn push(&mut self, commit: ClassifiedCommit) {
+        match commit.category {
+            Category::Feature => self.features.push(commit),
+            Category::Enhancement => self.enhancements.push(commit),
+            Category::Fix => self.fixes.push(commit),
+            Category::Other => self.other.push(commit)
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ClassifiedCommit> {
+        self.features
+            .iter()
+            .chain(&self.enhancements)
+            .chain(&self.fixes)
+            .chain(&self.other)
+    }
+
+    pub fn breaking_changes(&self) -> impl Iterator<Item = &ClassifiedCommit> {
+        self.iter().filter(|commit| commit.breaking_change.is_some())
+    }
+
+    pub fn len(&self) -> usize {
+        return self.features.len() + self.enhancements.len() + self.fixes.len() + self.other.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    // the top-level paths the commits touched, most lines changed first
+    pub fn components(&self) -> Vec<Component> {
+        let mut components: Vec<Component> = vec![];
+        for stat in self.iter().flat_map(|commit| &commit.diffstat.paths) {
+            match components.iter_mut().find(|component| component.path == stat.path) {
+                Some(component) => {
+                    component.commits += 1;
+                    component.insertions += stat.insertions;
+                    component.deletions += stat.deletions;
+                },
+                None => components.push(Component {
+                    path: stat.path.clone(),
+                    commits: 1,
+                    insertions: stat.insertions,
+                    deletions: stat.deletions
+                })
+            }
+        }
+        components.sort_by_key(|component| std::cmp::Reverse(component.insertions + component.deletions));
+
+        return components;
+    }
+
+    pub fn to_prompt(&self) -> String {
+        let mut sections = vec![];
+
+        // breaking changes are listed on their own in addition to their category so the model can't miss them
+        let breaking_changes = self
+            .breaking_changes()
+            .map(|commit| format!("- {}", commit.breaking_change.as_deref().unwrap_or(&commit.description)))
+            .collect::<Vec<_>>();
+        if !breaking_changes.is_empty() {
+            sections.push(format!("BREAKING CHANGES:\n{}", breaking_changes.join("\n")));
+        }
+
+        for (heading, group) in [
+            ("New features", &self.features),
+            ("Enhancements", &self.enhancements),
+            ("Fixes", &self.fixes),
+            ("Other changes", &self.other)
+        ] {
+            if !group.is_empty() {
+                sections.push(format!("{heading}:\n{}",
+                    group.iter().map(ClassifiedCommit::to_prompt).collect::<Vec<_>>().join("\n")));
+            }
+        }
+
+        return sections.join("\n\n");
+    }
+}
+
+// applied in order to the subject of commits that don't follow Conventional Commits, first match wins
+pub const DEFAULT_FALLBACK_PATTERNS: &[(&str, Category)] = &[
+    (r"^(merge|revert)\b", Category::Other),
+    (r"\b(fix(es|ed)?|bug(s)?|resolve[sd]?|crash(es)?|regression)\b", Category::Fix),
+    (r"^(add(s|ed)?|implement(s|ed)?|introduce[sd]?|support(s|ed)?|new)\b", Category::Feature),
+    (r"^(improve[sd]?|update[sd]?|optimi[sz]e[sd]?|refactor(s|ed)?|enhance[sd]?|simplif(y|ies|ied)|speed up)\b", Category::Enhancement)
+];
+
+pub struct Classifier {
+    header: Regex,
+    breaking_footer: Regex,
+    fallback_patterns: Vec<(Regex, Category)>
+}
+
+impl Classifier {
+    pub fn new<S: AsRef<str>>(fallback_patterns: &[(S, Category)]) -> Result<Self> {
+        let fallback_patterns = fallback_patterns
+            .iter()
+            .map(|(pattern, category)| {
+                let pattern = pattern.as_ref();
+                let regex = RegexBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|error| anyhow!("Invalid fallback pattern {pattern:?}: {error}"))?;
+                return Ok((regex, *category));
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        return Ok(Classifier {
+            // type(scope)!: description
+            header: Regex::new(r"^(?<type>[A-Za-z]+)(?:\((?<scope>[^()\r\n]*)\))?(?<breaking>!)?: (?<description>.+)$")?,
+            breaking_footer: Regex::new(r"(?m)^BREAKING[ -]CHANGE: (?<description>.+)$")?,
+            fallback_patterns
+        });
+    }
+
+    fn category_from_type(commit_type: &str) -> Category {
+        match commit_type.to_lowercase().as_str() {
+            "feat" | "feature" => Category::Feature,
+            "fix" | "bugfix" | "hotfix" => Category::Fix,
+            "perf" | "refactor" | "improvement" => Category::Enhancement,
+            _ => Category::Other
+        }
+    }
+
+    pub fn classify(&self, commit: &CommitInfo) -> ClassifiedCommit {
+        let id = commit.id.clone();
+        let diffstat = commit.diffstat.clone();
+        let message = commit.message.trim();
+        let (subject, body) = message.split_once('\n').unwrap_or((message, ""));
+        let subject = subject.trim();
+        let body = body.trim().to_string();
+        let footer_breaking_change = self.breaking_footer
+            .captures(&body)
+            .map(|captures| captures["description"].trim().to_string());
+
+        if let Some(captures) = self.header.captures(subject) {
+            let commit_type = captures["type"].to_string();
+            let description = captures["description"].trim().to_string();
+            let breaking_change = footer_breaking_change.or_else(||
+                captures.name("breaking").map(|_| description.clone()));
+
+            return ClassifiedCommit {
+                id,
+                diffstat,
+                category: Self::category_from_type(&commit_type),
+                commit_type: Some(commit_type),
+                scope: captures
+                    .name("scope")
+                    .map(|scope| scope.as_str().trim().to_string())
+                    .filter(|scope| !scope.is_empty()),
+                breaking_change,
+                description,
+                body
+            };
+        }
+
+        let category = self.fallback_patterns
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(subject))
+            .map(|(_, category)| *category)
+            .unwrap_or(Category::Other);
+
+        return ClassifiedCommit {
+            id,
+            diffstat,
+            category,
+            commit_type: None,
+            scope: None,
+            breaking_change: footer_breaking_change,
+            description: subject.to_string(),
+            body
+        };
+    }
+
+    pub fn categorize(&self, commits: &[CommitInfo]) -> CategorizedCommits {
+        let mut categorized = CategorizedCommits::default();
+        for commit in commits {
+            categorized.push(self.classify(commit));
+        }
+
+        return categorized;
+    }
+}
+
+impl Default for Classifier {
+    fn default() -> Self {
+        return Classifier::new(DEFAULT_FALLBACK_PATTERNS)
+            .expect("The default fallback patterns should always compile.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(message: &str) -> CommitInfo {
+        return CommitInfo {
+            id: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            author: "Jane Doe".to_string(),
+            author_email: "jane@example.com".to_string(),
+            date: chrono::DateTime::default(),
+            message: message.to_string(),
+            diffstat: DiffStat::default()
+        };
+    }
+
+    #[test]
+    fn parses_type_scope_and_description() {
+        let classified = Classifier::default().classify(&commit("fix(parser): handle empty input\n\nIt panicked before."));
+        assert_eq!(classified.category, Category::Fix);
+        assert_eq!(classified.commit_type.as_deref(), Some("fix"));
+        assert_eq!(classified.scope.as_deref(), Some("parser"));
+        assert_eq!(classified.description, "handle empty input");
+        assert_eq!(classified.body, "It panicked before.");
+        assert_eq!(classified.breaking_change, None);
+    }
+
+    #[test]
+    fn empty_scope_is_none() {
+        let classified = Classifier::default().classify(&commit("feat(): add export"));
+        assert_eq!(classified.category, Category::Feature);
+        assert_eq!(classified.scope, None);
+    }
+
+    #[test]
+    fn types_map_to_categories() {
+        let classifier = Classifier::default();
+        for (message, category) in [
+            ("feat: a", Category::Feature),
+            ("Feature: a", Category::Feature),
+            ("hotfix: a", Category::Fix),
+            ("perf: a", Category::Enhancement),
+            ("refactor(core): a", Category::Enhancement),
+            ("docs: a", Category::Other),
+            ("chore(deps): bump serde", Category::Other)
+        ] {
+            assert_eq!(classifier.classify(&commit(message)).category, category, "{message}");
+        }
+    }
+
+    #[test]
+    fn unknown_type_keeps_its_type() {
+        let classified = Classifier::default().classify(&commit("wip: fix the login bug"));
+        assert_eq!(classified.category, Category::Other);
+        assert_eq!(classified.commit_type.as_deref(), Some("wip"));
+        assert_eq!(classified.description, "fix the login bug");
+    }
+
+    #[test]
+    fn exclamation_mark_is_breaking() {
+        let classified = Classifier::default().classify(&commit("feat(api)!: drop the v1 endpoints"));
+        assert_eq!(classified.category, Category::Feature);
+        assert_eq!(classified.scope.as_deref(), Some("api"));
+        assert_eq!(classified.breaking_change.as_deref(), Some("drop the v1 endpoints"));
+    }
+
+    #[test]
+    fn breaking_footer_describes_the_change() {
+        let classifier = Classifier::default();
+        let classified = classifier.classify(&commit("feat!: new config\n\nBREAKING CHANGE: config.toml is no longer read"));
+        assert_eq!(classified.breaking_change.as_deref(), Some("config.toml is no longer read"));
+
+        let classified = classifier.classify(&commit("refactor: rename\n\nBREAKING-CHANGE: Client::send is now async"));
+        assert_eq!(classified.breaking_change.as_deref(), Some("Client::send is now async"));
+
+        // only at the start of a line, and in upper case
+        let classified = classifier.classify(&commit("fix: typo\n\nNo breaking change: none at all"));
+        assert_eq!(classified.breaking_change, None);
+    }
+
+    #[test]
+    fn breaking_footer_without_conventional_header() {
+        let classified = Classifier::default().classify(&commit("Remove the legacy API\n\nBREAKING CHANGE: /v1 is gone"));
+        assert_eq!(classified.commit_type, None);
+        assert_eq!(classified.breaking_change.as_deref(), Some("/v1 is gone"));
+    }
+
+    #[test]
+    fn header_needs_a_space_after_the_colon() {
+        let classified = Classifier::default().classify(&commit("fix:missing space"));
+        assert_eq!(classified.commit_type, None);
+        assert_eq!(classified.description, "fix:missing space");
+    }
+
+    #[test]
+    fn default_fallback_patterns() {
+        let classifier = Classifier::default();
+        for (message, category) in [
+            ("Fixed crash on startup", Category::Fix),
+            ("Resolves the login regression", Category::Fix),
+            ("Add dark mode", Category::Feature),
+            ("Implemented CSV export", Category::Feature),
+            ("Improve startup time", Category::Enhancement),
+            ("Speed up the search", Category::Enhancement),
+            ("Merge branch 'fix-login'", Category::Other),
+            ("Revert \"Add dark mode\"", Category::Other),
+            ("Bump version", Category::Other)
+        ] {
+            let classified = classifier.classify(&commit(message));
+            assert_eq!(classified.category, category, "{message}");
+            assert_eq!(classified.commit_type, None);
+            assert_eq!(classified.description, message);
+        }
+    }
+
+    #[test]
+    fn custom_fallback_patterns_in_order() {
+        let classifier = Classifier::new(&[(r"^wip\b", Category::Other), (r"\bticket\b", Category::Fix)]).unwrap();
+        assert_eq!(classifier.classify(&commit("WIP ticket handling")).category, Category::Other);
+        assert_eq!(classifier.classify(&commit("Close ticket")).category, Category::Fix);
+        assert_eq!(classifier.classify(&commit("Add dark mode")).category, Category::Other);
+    }
+
+    #[test]
+    fn invalid_fallback_pattern() {
+        let error = Classifier::new(&[("(unclosed", Category::Fix)]).err().unwrap();
+        assert!(error.to_string().contains("(unclosed"));
+    }
+
+    #[test]
+    fn categorize_groups_and_lists_breaking_changes() {
+        let commits = ["feat: a", "fix: b", "perf: c", "docs: d", "fix!: e"].map(commit);
+        let categorized = Classifier::default().categorize(&commits);
+        assert_eq!(categorized.features.len(), 1);
+        assert_eq!(categorized.fixes.len(), 2);
+        assert_eq!(categorized.enhancements.len(), 1);
+        assert_eq!(categorized.other.len(), 1);
+        assert_eq!(categorized.len(), 5);
+        assert!(categorized.to_prompt().starts_with("BREAKING CHANGES:\n- e\n\nNew features:\n- a"));
+    }
+}
@@ -0,0 +1,437 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+
+use crate::commits::{Category, Classifier, DEFAULT_FALLBACK_PATTERNS};
+use crate::credentials::Credentials;
+use crate::fetch_tokens::LlmConfig;
+use crate::contributors::{BotFilter, DEFAULT_BOT_PATTERNS};
+use crate::tickets::{TicketLinker, DEFAULT_REFERENCE_PATTERNS};
+
+// stored with every run, bump it whenever prompt.txt, summarize.txt, release-notes.json or generate_prompt change
+pub const PROMPT_VERSION: &str = "5";
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReposConfig {
+    pub path: PathBuf,
+    pub max_size_mb: Option<u64>        // the least recently used repos are removed above this, unlimited if unset
+}
+
+impl Default for ReposConfig {
+    fn default() -> Self {
+        return ReposConfig { path: PathBuf::from("./repos"), max_size_mb: None };
+    }
+}
+
+// what the repo cache leaves out of clones, anything but none needs the git executable since libgit2 can't clone
+// partially. remotes that don't support filters are cloned in full
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PartialClone {
+    None,
+    Blobless,
+    Treeless
+}
+
+impl PartialClone {
+    pub fn filter(self) -> Option<&'static str> {
+        match self {
+            PartialClone::None => None,
+            PartialClone::Blobless => Some("blob:none"),
+            PartialClone::Treeless => Some("tree:0")
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitConfig {
+    pub partial_clone: PartialClone,
+    pub executable: PathBuf,            // only run for partial clones
+    pub allowed_schemes: Vec<String>,
+    pub allowed_hosts: Vec<String>,     // any host may be cloned from if empty
+    pub allow_local_paths: bool,        // file:// links and paths on the server
+    pub allow_private_addresses: bool,  // hosts resolving to loopback, private or link-local addresses
+    pub max_clone_size_mb: u64,         // per clone or fetch, unlike repos.max_size_mb
+    pub clone_timeout_secs: u64,
+    pub credentials: Option<PathBuf>    // a JSON file, see Credentials
+}
+
+impl Default for GitConfig {
+    fn default() -> Self {
+        return GitConfig {
+            partial_clone: PartialClone::None,
+            executable: PathBuf::from("git"),
+            allowed_schemes: vec!["https".to_string(), "ssh".to_string()],
+            allowed_hosts: vec![],
+            allow_local_paths: false,
+            allow_private_addresses: false,
+            max_clone_size_mb: 1024,
+            clone_timeout_secs: 300,
+            credentials: None
+        };
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_concurrent_jobs: usize      // further generations wait for a running one to finish
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        return LimitsConfig { max_concurrent_jobs: 4 };
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommitsConfig {
+    pub fallback_patterns: Vec<(String, Category)>  // regular expressions and their category, see Classifier
+}
+
+impl Default for CommitsConfig {
+    fn default() -> Self {
+        return CommitsConfig {
+            fallback_patterns: DEFAULT_FALLBACK_PATTERNS
+                .iter()
+                .map(|(pattern, category)| (pattern.to_string(), *category))
+                .collect()
+        };
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TicketsConfig {
+    pub reference_patterns: Vec<String>     // regular expressions with a group named id, see TicketLinker
+}
+
+impl Default for TicketsConfig {
+    fn default() -> Self {
+        return TicketsConfig {
+            reference_patterns: DEFAULT_REFERENCE_PATTERNS.iter().map(|pattern| pattern.to_string()).collect()
+        };
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ContributorsConfig {
+    pub bot_patterns: Vec<String>           // regular expressions matched against "name <email>", see BotFilter
+}
+
+impl Default for ContributorsConfig {
+    fn default() -> Self {
+        return ContributorsConfig {
+            bot_patterns: DEFAULT_BOT_PATTERNS.iter().map(|pattern| pattern.to_string()).collect()
+        };
+    }
+}
+
+// paths to replace the built-in templates in ./src/templates with
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PromptsConfig {
+    pub system: Option<PathBuf>,
+    pub summarize: Option<PathBuf>,
+    pub release_notes_format: Option<PathBuf>
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    pub database: PathBuf
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        return HistoryConfig { database: PathBuf::from("./releasenotes.db") };
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    pub token: Option<String>           // the /api/v1/admin endpoints are disabled without one
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Templates {
+    pub system: String,
+    pub summarize: String,
+    pub release_notes_format: String,
+    pub custom: bool
+}
+
+impl Templates {
+    pub fn version(&self) -> String {
+        match self.custom {
+            true => format!("{PROMPT_VERSION}-custom"),
+            false => PROMPT_VERSION.to_string()
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub repos: ReposConfig,
+    pub llm: LlmConfig,
+    pub git: GitConfig,
+    pub limits: LimitsConfig,
+    pub commits: CommitsConfig,
+    pub tickets: TicketsConfig,
+    pub contributors: ContributorsConfig,
+    pub prompts: PromptsConfig,
+    pub history: HistoryConfig,
+    pub admin: AdminConfig,
+    // read from the files above once the config has been loaded
+    #[serde(skip)]
+    pub credentials: Credentials,
+    #[serde(skip)]
+    pub templates: Templates
+}
+
+pub(crate) fn parse_env<T: std::str::FromStr>(name: &str, default: T) -> Result<T> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| anyhow!("Invalid value for {name}: {value}")),
+        Err(_) => Ok(default)
+    }
+}
+
+fn parse_list(value: &str) -> Vec<String> {
+    return value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect();
+}
+
+// regular expressions may contain commas, as in {2,4}, so they are given as a JSON array or one per line
+fn parse_patterns(name: &str, value: &str) -> Result<Vec<String>> {
+    if value.trim_start().starts_with('[') {
+        return serde_json::from_str(value).map_err(|error| anyhow!("Invalid value for {name}: {error}"));
+    }
+
+    return Ok(value
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect());
+}
+
+fn read_template(path: Option<&Path>, default: &str) -> Result<String> {
+    let Some(path) = path else {
+        return Ok(default.to_string());
+    };
+
+    return std::fs::read_to_string(path)
+        .map_err(|error| anyhow!("Unable to read prompt template {}: {error}", path.display()));
+}
+
+impl Config {
+    // RELEASENOTES_CONFIG points to the TOML file, ./releasenotes.toml is used if it exists and defaults otherwise
+    pub fn load() -> Result<Self> {
+        let mut config = match std::env::var("RELEASENOTES_CONFIG") {
+            Ok(path) => Config::from_file(Path::new(&path))?,
+            Err(_) if Path::new("./releasenotes.toml").exists() => Config::from_file(Path::new("./releasenotes.toml"))?,
+            Err(_) => Config::default()
+        };
+
+        config.apply_env()?;
+        config.llm.apply_env()?;
+        config.validate()?;
+
+        config.credentials = match &config.git.credentials {
+            Some(path) => Credentials::load(path)?,
+            None => Credentials::default()
+        };
+        config.templates = Templates {
+            system: read_template(config.prompts.system.as_deref(), include_str!("./templates/prompt.txt"))?,
+            summarize: read_template(config.prompts.summarize.as_deref(), include_str!("./templates/summarize.txt"))?,
+            release_notes_format: read_template(
+                config.prompts.release_notes_format.as_deref(),
+                include_str!("./templates/release-notes.json"))?,
+            custom: config.prompts.system.is_some()
+                || config.prompts.summarize.is_some()
+                || config.prompts.release_notes_format.is_some()
+        };
+
+        return Ok(config);
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|error| anyhow!("Unable to read config file {}: {error}", path.display()))?;
+        return toml::from_str(&contents)
+            .map_err(|error| anyhow!("Invalid config file {}: {error}", path.display()));
+    }
+
+    fn apply_env(&mut self) -> Result<()> {
+        if let Ok(path) = std::env::var("RELEASENOTES_REPOS_DIR") {
+            self.repos.path = PathBuf::from(path);
+        }
+        if let Ok(max_size_mb) = std::env::var("RELEASENOTES_REPOS_MAX_SIZE_MB") {
+            self.repos.max_size_mb = Some(max_size_mb
+                .parse()
+                .map_err(|_| anyhow!("Invalid value for RELEASENOTES_REPOS_MAX_SIZE_MB: {max_size_mb}"))?);
+        }
+        if let Ok(partial_clone) = std::env::var("RELEASENOTES_PARTIAL_CLONE") {
+            self.git.partial_clone = serde_json::from_value(Value::String(partial_clone.clone()))
+                .map_err(|_| anyhow!("Unknown partial clone {partial_clone}, expected none, blobless or treeless."))?;
+        }
+        if let Ok(executable) = std::env::var("RELEASENOTES_GIT") {
+            self.git.executable = PathBuf::from(executable);
+        }
+        if let Ok(schemes) = std::env::var("RELEASENOTES_ALLOWED_SCHEMES") {
+            self.git.allowed_schemes = parse_list(&schemes);
+        }
+        if let Ok(hosts) = std::env::var("RELEASENOTES_ALLOWED_HOSTS") {
+            self.git.allowed_hosts = parse_list(&hosts);
+        }
+        self.git.allow_local_paths = parse_env("RELEASENOTES_ALLOW_LOCAL_PATHS", self.git.allow_local_paths)?;
+        self.git.allow_private_addresses = parse_env("RELEASENOTES_ALLOW_PRIVATE_ADDRESSES", self.git.allow_private_addresses)?;
+        self.git.max_clone_size_mb = parse_env("RELEASENOTES_MAX_CLONE_SIZE_MB", self.git.max_clone_size_mb)?;
+        self.git.clone_timeout_secs = parse_env("RELEASENOTES_CLONE_TIMEOUT_SECS", self.git.clone_timeout_secs)?;
+        if let Ok(path) = std::env::var("RELEASENOTES_CREDENTIALS") {
+            self.git.credentials = Some(PathBuf::from(path));
+        }
+        self.limits.max_concurrent_jobs = parse_env("RELEASENOTES_MAX_CONCURRENT_JOBS", self.limits.max_concurrent_jobs)?;
+        // a JSON array of [pattern, category] pairs
+        if let Ok(patterns) = std::env::var("RELEASENOTES_FALLBACK_PATTERNS") {
+            self.commits.fallback_patterns = serde_json::from_str(&patterns)
+                .map_err(|error| anyhow!("Invalid value for RELEASENOTES_FALLBACK_PATTERNS: {error}"))?;
+        }
+        if let Ok(patterns) = std::env::var("RELEASENOTES_TICKET_PATTERNS") {
+            self.tickets.reference_patterns = parse_patterns("RELEASENOTES_TICKET_PATTERNS", &patterns)?;
+        }
+        if let Ok(patterns) = std::env::var("RELEASENOTES_BOT_PATTERNS") {
+            self.contributors.bot_patterns = parse_patterns("RELEASENOTES_BOT_PATTERNS", &patterns)?;
+        }
+        if let Ok(path) = std::env::var("RELEASENOTES_DB") {
+            self.history.database = PathBuf::from(path);
+        }
+        if let Ok(token) = std::env::var("RELEASENOTES_ADMIN_TOKEN") {
+            self.admin.token = Some(token);
+        }
+
+        return Ok(());
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.repos.path.as_os_str().is_empty() {
+            return Err(anyhow!("repos.path must not be empty."));
+        }
+        // created with the first clone
+        if self.repos.path.exists() && !self.repos.path.is_dir() {
+            return Err(anyhow!("repos.path {} isn't a directory.", self.repos.path.display()));
+        }
+        if self.repos.max_size_mb == Some(0) {
+            return Err(anyhow!("repos.max_size_mb must be at least 1, leave it out for no limit."));
+        }
+
+        for scheme in &self.git.allowed_schemes {
+            if !["https", "http", "ssh", "git"].contains(&scheme.to_ascii_lowercase().as_str()) {
+                return Err(anyhow!(
+                    "git.allowed_schemes takes https, http, ssh or git, not {scheme:?}. \
+                    Local repositories are enabled with git.allow_local_paths."));
+            }
+        }
+        for host in &self.git.allowed_hosts {
+            if host.is_empty() || host.contains("://") || host.contains('/') {
+                return Err(anyhow!("git.allowed_hosts takes host names such as github.com, not {host:?}."));
+            }
+        }
+
+        if self.git.max_clone_size_mb == 0 {
+            return Err(anyhow!("git.max_clone_size_mb must be at least 1."));
+        }
+        if self.git.clone_timeout_secs == 0 {
+            return Err(anyhow!("git.clone_timeout_secs must be at least 1."));
+        }
+
+        if self.limits.max_concurrent_jobs == 0 {
+            return Err(anyhow!("limits.max_concurrent_jobs must be at least 1."));
+        }
+
+        Classifier::new(&self.commits.fallback_patterns)
+            .map_err(|error| anyhow!("commits.fallback_patterns: {error}"))?;
+        TicketLinker::new(&self.tickets.reference_patterns)
+            .map_err(|error| anyhow!("tickets.reference_patterns: {error}"))?;
+        BotFilter::new(&self.contributors.bot_patterns)
+            .map_err(|error| anyhow!("contributors.bot_patterns: {error}"))?;
+
+        if self.admin.token.as_ref().is_some_and(|token| token.trim().len() < 16) {
+            return Err(anyhow!("admin.token must be at least 16 characters long."));
+        }
+
+        return self.llm.validate();
+    }
+
+    // what only fails once a generation starts, so the server still starts and serves the history and admin API
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = vec![];
+        if let Err(error) = self.llm.provider() {
+            warnings.push(format!("Release notes can't be generated until the LLM is configured: {error}."));
+        }
+        if self.git.partial_clone != PartialClone::None {
+            let found = Command::new(&self.git.executable)
+                .arg("--version")
+                .output()
+                .is_ok_and(|output| output.status.success());
+            if !found {
+                warnings.push(format!(
+                    "git.partial_clone needs git at {}, set git.executable or use partial_clone = \"none\".",
+                    self.git.executable.display()));
+            }
+        }
+
+        return warnings;
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+// loads and validates the config, called once at startup so mistakes surface before the first request
+pub fn init() -> Result<&'static Config> {
+    let config = Config::load()?;
+    crate::git::allow_partial_clones()?;
+    return Ok(CONFIG.get_or_init(|| config));
+}
+
+pub fn get() -> &'static Config {
+    return CONFIG.get().expect("config::init should be called at startup.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_are_comma_separated() {
+        assert_eq!(parse_list(" github.com, gitlab.example.com ,,"), ["github.com", "gitlab.example.com"]);
+    }
+
+    #[test]
+    fn patterns_keep_their_commas() {
+        let patterns = parse_patterns("PATTERNS", r##"["\\b(?<id>[A-Z]{2,5}-\\d+)\\b", "#(?<id>\\d+)"]"##).unwrap();
+        assert_eq!(patterns, [r"\b(?<id>[A-Z]{2,5}-\d+)\b", r"#(?<id>\d+)"]);
+
+        let patterns = parse_patterns("PATTERNS", "\\[bot\\]\n  ^ci-{1,2}\n\n").unwrap();
+        assert_eq!(patterns, [r"\[bot\]", "^ci-{1,2}"]);
+    }
+
+    #[test]
+    fn invalid_pattern_array() {
+        let error = parse_patterns("RELEASENOTES_BOT_PATTERNS", "[\"unclosed").err().unwrap();
+        assert!(error.to_string().contains("RELEASENOTES_BOT_PATTERNS"));
+    }
+}
@@ -0,0 +1,131 @@
+use anyhow::{anyhow, Result};
+use regex::{Regex, RegexBuilder};
+
+// matched against "name <email>" regardless of case: GitHub apps, common dependency bots, and the committer GitHub
+// uses for changes made in its web interface
+pub const DEFAULT_BOT_PATTERNS: &[&str] = &[
+    r"\[bot\]",
+    r"^(dependabot|renovate|github-actions|greenkeeper|snyk-bot|pre-commit-ci)\b",
+    r"<noreply@github\.com>$"
+];
+
+// the names and emails of the Co-authored-by: trailers of a commit message
+pub fn co_authors(message: &str) -> Vec<(String, String)> {
+    let mut co_authors = vec![];
+    for line in message.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        if !key.trim().eq_ignore_ascii_case("co-authored-by") {
+            continue;
+        }
+
+        let Some((name, rest)) = value.split_once('<') else {
+            continue;
+        };
+        let Some((email, _)) = rest.split_once('>') else {
+            continue;
+        };
+        // trailers without a name or an email credit nobody
+        let (name, email) = (name.trim(), email.trim());
+        if name.is_empty() || email.is_empty() {
+            continue;
+        }
+        co_authors.push((name.to_string(), email.to_string()));
+    }
+
+    return co_authors;
+}
+
+pub struct BotFilter {
+    patterns: Vec<Regex>
+}
+
+impl BotFilter {
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<Self> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                let pattern = pattern.as_ref();
+                return RegexBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|error| anyhow!("Invalid bot pattern {pattern:?}: {error}"));
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        return Ok(BotFilter { patterns });
+    }
+
+    pub fn is_bot(&self, name: &str, email: &str) -> bool {
+        let identity = format!("{name} <{email}>");
+        return self.patterns.iter().any(|pattern| pattern.is_match(&identity));
+    }
+}
+
+impl Default for BotFilter {
+    fn default() -> Self {
+        return BotFilter::new(DEFAULT_BOT_PATTERNS)
+            .expect("The default bot patterns should always compile.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_co_authors() {
+        let message = "feat: add export\n\nCo-authored-by: Jane Doe <jane@example.com>\nco-authored-by:John Roe<john@example.com>  ";
+        assert_eq!(co_authors(message), [
+            ("Jane Doe".to_string(), "jane@example.com".to_string()),
+            ("John Roe".to_string(), "john@example.com".to_string())
+        ]);
+    }
+
+    #[test]
+    fn skips_malformed_trailers() {
+        let message = [
+            "fix: handle empty input",
+            "",
+            "Co-authored-by: Jane Doe",
+            "Co-authored-by: <jane@example.com>",
+            "Co-authored-by: Jane Doe <>",
+            "Co-authored-by: Jane Doe <jane@example.com",
+            "Co-authored by: Jane Doe <jane@example.com>",
+            "Reviewed-by: John Roe <john@example.com>"
+        ].join("\n");
+        assert!(co_authors(&message).is_empty());
+    }
+
+    #[test]
+    fn ignores_text_after_the_email() {
+        let message = "Co-authored-by: Jane Doe <jane@example.com> (pairing)";
+        assert_eq!(co_authors(message), [("Jane Doe".to_string(), "jane@example.com".to_string())]);
+    }
+
+    #[test]
+    fn recognizes_bots() {
+        let bots = BotFilter::default();
+        assert!(bots.is_bot("dependabot[bot]", "49699333+dependabot[bot]@users.noreply.github.com"));
+        assert!(bots.is_bot("Renovate", "bot@renovateapp.com"));
+        assert!(bots.is_bot("GitHub", "noreply@github.com"));
+        assert!(bots.is_bot("GitHub", "NoReply@GitHub.com"));
+    }
+
+    #[test]
+    fn bot_patterns_are_anchored() {
+        let bots = BotFilter::default();
+        // the names only match at the start, and the GitHub committer only as the whole email
+        assert!(!bots.is_bot("Jane Dependabot", "jane@example.com"));
+        assert!(!bots.is_bot("Renovated Kitchens", "info@example.com"));
+        assert!(!bots.is_bot("Jane Doe", "12345+jane@users.noreply.github.com"));
+        assert!(!bots.is_bot("Jane Doe", "jane.noreply@github.com"));
+        assert!(!bots.is_bot("Jane Doe", "noreply@github.com.example.com"));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        assert!(BotFilter::new(&["(unclosed"]).is_err());
+    }
+}
@@ -1,11 +1,184 @@
 use anyhow::{anyhow, Result};
-use futures::{Stream, StreamExt};
+use futures::{stream, Stream, StreamExt};
 use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
 use reqwest_eventsource::{Event, EventSource};
+use serde::Deserialize;
 use serde_json::{json, Value};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
-fn parse_message(message: &str) -> Result<String> {
-    let token: Option<String> = serde_json::from_str::<Value>(&message)
+use crate::config::parse_env;
+use crate::summarize::estimate_tokens;
+use crate::util::Usage;
+
+// Ok(None) marks the end of the stream
+pub type TokenStream = Pin<Box<dyn Stream<Item = Result<Option<String>>> + Send>>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResponseFormat {
+    Text,
+    Json
+}
+
+pub trait Provider: Send + Sync {
+    fn model(&self) -> &str;
+    fn fetch_tokens(&self, prompt: &str, system_prompt: &str, format: ResponseFormat) -> TokenStream;
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum ProviderKind {
+    #[serde(rename = "openai")]
+    OpenAi,
+    #[serde(rename = "anthropic")]
+    Anthropic,
+    #[serde(rename = "mock")]
+    Mock
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LlmConfig {
+    pub provider: ProviderKind,
+    pub model: String,                  // the provider's default model if empty
+    pub base_url: Option<String>,
+    pub api_key: Option<String>,
+    pub temperature: f32,
+    pub max_tokens: u32,
+    pub context_tokens: usize           // the size of the model's context window, prompt and output combined
+}
+
+impl Default for LlmConfig {
+    fn default() -> Self {
+        return LlmConfig {
+            provider: ProviderKind::OpenAi,
+            model: "".to_string(),
+            base_url: None,
+            api_key: None,
+            temperature: 1.0,
+            max_tokens: 2048,
+            context_tokens: 128000
+        };
+    }
+}
+
+impl LlmConfig {
+    // the LLM_ variables override the config file, the provider's own key variable is only used if neither sets a key
+    pub fn apply_env(&mut self) -> Result<()> {
+        if let Ok(provider) = std::env::var("LLM_PROVIDER") {
+            self.provider = serde_json::from_value(Value::String(provider.clone()))
+                .map_err(|_| anyhow!("Unknown LLM provider {provider}, expected openai, anthropic or mock."))?;
+        }
+        if let Ok(model) = std::env::var("LLM_MODEL") {
+            self.model = model;
+        }
+        if let Ok(base_url) = std::env::var("LLM_BASE_URL") {
+            self.base_url = Some(base_url);
+        }
+        if let Ok(api_key) = std::env::var("LLM_API_KEY") {
+            self.api_key = Some(api_key);
+        }
+        self.temperature = parse_env("LLM_TEMPERATURE", self.temperature)?;
+        self.max_tokens = parse_env("LLM_MAX_TOKENS", self.max_tokens)?;
+        self.context_tokens = parse_env("LLM_CONTEXT_TOKENS", self.context_tokens)?;
+
+        let (default_model, api_key_var) = match self.provider {
+            ProviderKind::OpenAi => ("gpt-4-turbo", "OPENAI_API_KEY"),
+            ProviderKind::Anthropic => ("claude-3-opus-20240229", "ANTHROPIC_API_KEY"),
+            ProviderKind::Mock => ("mock", "LLM_API_KEY")
+        };
+        if self.model.is_empty() {
+            self.model = default_model.to_string();
+        }
+        if self.api_key.is_none() {
+            self.api_key = std::env::var(api_key_var).ok();
+        }
+
+        return Ok(());
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=2.0).contains(&self.temperature) {
+            return Err(anyhow!("llm.temperature must be between 0 and 2, not {}.", self.temperature));
+        }
+        if self.max_tokens == 0 {
+            return Err(anyhow!("llm.max_tokens must be at least 1."));
+        }
+        if self.context_tokens <= self.max_tokens as usize {
+            return Err(anyhow!("llm.context_tokens ({}) must be larger than llm.max_tokens ({}).", self.context_tokens, self.max_tokens));
+        }
+
+        // a missing API key is only a warning at startup, see Config::warnings, and an error once a generation starts
+        return Ok(());
+    }
+
+    // how many tokens a prompt may take up while leaving room for the output
+    pub fn prompt_budget(&self) -> usize {
+        return self.context_tokens.saturating_sub(self.max_tokens as usize);
+    }
+
+    pub fn provider(&self) -> Result<Box<dyn Provider>> {
+        match self.provider {
+            ProviderKind::OpenAi => {
+                // local OpenAI-compatible servers such as llama.cpp or Ollama usually don't need a key
+                if self.api_key.is_none() && self.base_url.is_none() {
+                    return Err(anyhow!("No OpenAI API Key"));
+                }
+
+                return Ok(Box::new(OpenAiCompatible {
+                    base_url: self.base_url.clone().unwrap_or("https://api.openai.com/v1".to_string()),
+                    api_key: self.api_key.clone(),
+                    model: self.model.clone(),
+                    temperature: self.temperature,
+                    max_tokens: self.max_tokens
+                }));
+            },
+            ProviderKind::Anthropic => {
+                return Ok(Box::new(Anthropic {
+                    base_url: self.base_url.clone().unwrap_or("https://api.anthropic.com".to_string()),
+                    api_key: self.api_key.clone().ok_or(anyhow!("No Anthropic API Key"))?,
+                    model: self.model.clone(),
+                    temperature: self.temperature,
+                    max_tokens: self.max_tokens
+                }));
+            },
+            ProviderKind::Mock => {
+                return Ok(Box::new(Mock::default()));
+            }
+        }
+    }
+}
+
+fn stream_events<F>(request_builder: reqwest::RequestBuilder, parse_event: F) -> TokenStream
+where F: Fn(&str, &str) -> Result<Option<String>> + Send + 'static {
+    let event_source = match EventSource::new(request_builder) {
+        Ok(event_source) => event_source,
+        Err(error) => return stream::once(async move { Err(anyhow!("{error}")) }).boxed()
+    };
+
+    return event_source.map(move |event| {
+        match event {
+            Ok(Event::Open) => Ok(Some("".to_string())),
+            Ok(Event::Message(message)) => parse_event(&message.event, &message.data),
+            Err(reqwest_eventsource::Error::StreamEnded) => Ok(None),
+            Err(error) => Err(anyhow!("{error}"))
+        }}).boxed();
+}
+
+pub struct OpenAiCompatible {
+    pub base_url: String,
+    pub api_key: Option<String>,
+    pub model: String,
+    pub temperature: f32,
+    pub max_tokens: u32
+}
+
+fn parse_openai_message(message: &str) -> Result<Option<String>> {
+    if message.trim() == "[DONE]" {
+        return Ok(None);
+    }
+
+    let token: Option<String> = serde_json::from_str::<Value>(message)
         .ok()
         .and_then(|data| {
             if !data["choices"][0]["finish_reason"].is_null() {
@@ -19,47 +192,196 @@ fn parse_message(message: &str) -> Result<String> {
         });
 
     if let Some(token) = token {
-        return Ok(token.to_string());
+        return Ok(Some(token));
     } else {
         return Err(anyhow::anyhow!("Error parsing response."));
     }
 }
 
-pub fn fetch_tokens(
-    api_key: &str,
-    prompt: &str,
-    system_prompt:&str 
-) -> impl Stream<Item = Result<Option<String>>> {
-    let mut headers = HeaderMap::new();
-    headers.insert("Authorization", HeaderValue::from_str(&format!("Bearer {}", api_key)).unwrap());
-    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+impl Provider for OpenAiCompatible {
+    fn model(&self) -> &str {
+        &self.model
+    }
 
-    let request_builder = reqwest::Client::new()
-        .post("https://api.openai.com/v1/chat/completions")
-        .headers(headers)
-        .body(json!({
-            "model": "gpt-4-turbo",
-            "max_tokens": 2048,
-            "temperature": 1,
+    fn fetch_tokens(&self, prompt: &str, system_prompt: &str, format: ResponseFormat) -> TokenStream {
+        let mut headers = HeaderMap::new();
+        if let Some(api_key) = &self.api_key {
+            match HeaderValue::from_str(&format!("Bearer {api_key}")) {
+                Ok(authorization) => headers.insert("Authorization", authorization),
+                Err(_) => return stream::once(async { Err(anyhow!("Invalid API key.")) }).boxed()
+            };
+        }
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+        let mut body = json!({
+            "model": self.model,
+            "max_tokens": self.max_tokens,
+            "temperature": self.temperature,
             "stream": true,
             "messages": [
                 { "role": "system", "content": system_prompt },
                 { "role": "user", "content": prompt }
             ]
-        }).to_string());
+        });
+        if format == ResponseFormat::Json {
+            body["response_format"] = json!({ "type": "json_object" });
+        }
 
-    let event_source = EventSource::new(request_builder).unwrap();
-    return event_source.map(|event| {
-        match event {
-            Ok(Event::Open) => Ok(Some("".to_string())),
-            Ok(Event::Message(message)) => {
-                if message.data.trim() == "[DONE]" {
-                    return Ok(None);
-                }
+        let request_builder = reqwest::Client::new()
+            .post(format!("{}/chat/completions", self.base_url.trim_end_matches('/')))
+            .headers(headers)
+            .body(body.to_string());
 
-                Ok(Some(parse_message(&message.data)?))
-            }
-            Err(reqwest_eventsource::Error::StreamEnded) => Ok(None),
-            Err(error) => Err(anyhow!("{error}"))
-        }});
-}
\ No newline at end of file
+        return stream_events(request_builder, |_, data| parse_openai_message(data));
+    }
+}
+
+pub struct Anthropic {
+    pub base_url: String,
+    pub api_key: String,
+    pub model: String,
+    pub temperature: f32,
+    pub max_tokens: u32
+}
+
+// https://docs.anthropic.com/en/api/messages-streaming
+fn parse_anthropic_event(event: &str, data: &str) -> Result<Option<String>> {
+    let data = serde_json::from_str::<Value>(data)
+        .map_err(|_| anyhow!("Error parsing response."))?;
+
+    match event {
+        "content_block_delta" => data["delta"]["text"]
+            .as_str()
+            .map(|token| Some(token.to_string()))
+            .ok_or(anyhow!("Error parsing response.")),
+        "message_stop" => Ok(None),
+        "error" => Err(anyhow!("{}", data["error"]["message"].as_str().unwrap_or("Unknown error."))),
+        // message_start, content_block_start, ping and the like carry no text
+        _ => Ok(Some("".to_string()))
+    }
+}
+
+impl Provider for Anthropic {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn fetch_tokens(&self, prompt: &str, system_prompt: &str, format: ResponseFormat) -> TokenStream {
+        let mut headers = HeaderMap::new();
+        match HeaderValue::from_str(&self.api_key) {
+            Ok(api_key) => headers.insert("x-api-key", api_key),
+            Err(_) => return stream::once(async { Err(anyhow!("Invalid API key.")) }).boxed()
+        };
+        headers.insert("anthropic-version", HeaderValue::from_static("2023-06-01"));
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+        let mut messages = vec![json!({ "role": "user", "content": prompt })];
+        // there's no JSON mode, but prefilling the response with the opening brace has the same effect
+        if format == ResponseFormat::Json {
+            messages.push(json!({ "role": "assistant", "content": "{" }));
+        }
+
+        let request_builder = reqwest::Client::new()
+            .post(format!("{}/v1/messages", self.base_url.trim_end_matches('/')))
+            .headers(headers)
+            .body(json!({
+                "model": self.model,
+                "max_tokens": self.max_tokens,
+                "temperature": self.temperature,
+                "stream": true,
+                "system": system_prompt,
+                "messages": messages
+            }).to_string());
+
+        let prefill = match format {
+            ResponseFormat::Text => "",
+            ResponseFormat::Json => "{"
+        };
+        return stream::once(async move { Ok(Some(prefill.to_string())) })
+            .chain(stream_events(request_builder, parse_anthropic_event))
+            .boxed();
+    }
+}
+
+// streams a fixed response word by word, so the rest of the pipeline can run without an API
+pub struct Mock {
+    pub response: String,
+    pub json_response: String
+}
+
+impl Default for Mock {
+    fn default() -> Self {
+        return Mock {
+            response: "- Feature 1\n- Enhancement 1\n- Fix 1".to_string(),
+            json_response: include_str!("./templates/release-notes.json").to_string()
+        };
+    }
+}
+
+impl Provider for Mock {
+    fn model(&self) -> &str {
+        "mock"
+    }
+
+    fn fetch_tokens(&self, _prompt: &str, _system_prompt: &str, format: ResponseFormat) -> TokenStream {
+        let response = match format {
+            ResponseFormat::Text => &self.response,
+            ResponseFormat::Json => &self.json_response
+        };
+        let tokens = response
+            .split_inclusive(' ')
+            .map(|token| Ok(Some(token.to_string())))
+            .chain([Ok(None)])
+            .collect::<Vec<_>>();
+
+        return stream::iter(tokens).boxed();
+    }
+}
+
+// counts the calls made through a provider and the tokens sent and received
+pub struct Metered<'a> {
+    provider: &'a dyn Provider,
+    llm_calls: AtomicUsize,
+    prompt_tokens: AtomicUsize,
+    completion_chars: Arc<AtomicUsize>
+}
+
+impl<'a> Metered<'a> {
+    pub fn new(provider: &'a dyn Provider) -> Self {
+        return Metered {
+            provider,
+            llm_calls: AtomicUsize::new(0),
+            prompt_tokens: AtomicUsize::new(0),
+            completion_chars: Arc::new(AtomicUsize::new(0))
+        };
+    }
+
+    pub fn usage(&self) -> Usage {
+        return Usage {
+            llm_calls: self.llm_calls.load(Ordering::Relaxed),
+            prompt_tokens: self.prompt_tokens.load(Ordering::Relaxed),
+            completion_tokens: self.completion_chars.load(Ordering::Relaxed).div_ceil(4)
+        };
+    }
+}
+
+impl Provider for Metered<'_> {
+    fn model(&self) -> &str {
+        self.provider.model()
+    }
+
+    fn fetch_tokens(&self, prompt: &str, system_prompt: &str, format: ResponseFormat) -> TokenStream {
+        self.llm_calls.fetch_add(1, Ordering::Relaxed);
+        self.prompt_tokens.fetch_add(estimate_tokens(prompt) + estimate_tokens(system_prompt), Ordering::Relaxed);
+
+        let completion_chars = self.completion_chars.clone();
+        return self.provider
+            .fetch_tokens(prompt, system_prompt, format)
+            .inspect(move |token| {
+                if let Ok(Some(token)) = token {
+                    completion_chars.fetch_add(token.len(), Ordering::Relaxed);
+                }
+            })
+            .boxed();
+    }
+}
@@ -1,33 +1,132 @@
-use anyhow::Result;
 use chrono::NaiveDate;
 use leptos::*;
+use std::time::Duration;
 
 use wasm_bindgen::prelude::*;
-use web_sys::{js_sys, ErrorEvent, MessageEvent, WebSocket};
+use web_sys::{js_sys, MessageEvent, WebSocket};
 
+use crate::tag_picker::{list_tags, TagPicker};
 use crate::ticket_form::TicketForm;
-use crate::util::{Arguments, TargetAudience, Ticket};
+use crate::util::{
+    previous_tag, Arguments, ClientMessage, Envelope, ErrorKind, ReleaseNotes, RemovedCommit, ServerMessage, TargetAudience, Ticket,
+    TicketLinkReport, TransferProgress, PROTOCOL_VERSION
+};
 
-#[derive(Clone, Debug)]
-enum Progress {
-    Cloning,
-    Streaming
+#[derive(Clone, Debug, Default)]
+struct Progress {
+    stage: String,
+    percent: Option<u8>,
+    commits: Option<usize>,
+    transfer: Option<TransferProgress>,     // of the clone or fetch, until the next stage starts
+    tokens: usize           // tokens of the release notes received so far
 }
 
+impl Progress {
+    fn label(&self) -> String {
+        let mut label = self.stage.clone();
+        if let Some(percent) = self.percent {
+            label += &format!(" ({percent}%)");
+        }
+        if let Some(transfer) = self.transfer {
+            label += &format!(" ({})", transfer.label());
+        }
+        if self.tokens > 0 {
+            label += &format!(" ({} tokens)", self.tokens);
+        }
+        if let Some(commits) = self.commits {
+            label = format!("{commits} commits, {label}");
+        }
+
+        return label;
+    }
+}
+
+fn error_label(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::InvalidRequest => "Invalid request",
+        ErrorKind::RepoNotAllowed => "Repository not allowed",
+        ErrorKind::RepoTooLarge => "Repository too large",
+        ErrorKind::CloneTimedOut => "Clone timed out",
+        ErrorKind::Git => "Git error",
+        ErrorKind::Llm => "LLM error",
+        ErrorKind::Cancelled => "Cancelled",
+        ErrorKind::Internal => "Server error"
+    }
+}
+
+
+// the job of the current generation is kept for the tab, so it can be attached to again after a reload
+const JOB_STORAGE_KEY: &str = "releasenotes-job";
+const MAX_RECONNECT_ATTEMPTS: u32 = 5;
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
 
-fn setup_callbacks(
-    web_socket: &mut WebSocket,
-    arguments: Arguments,
-    set_release_notes: WriteSignal<String>,
+fn session_storage() -> Option<web_sys::Storage> {
+    return window().session_storage().ok().flatten();
+}
+
+fn stored_job_id() -> Option<String> {
+    return session_storage()?.get_item(JOB_STORAGE_KEY).ok().flatten();
+}
+
+fn store_job_id(job_id: Option<&str>) {
+    let Some(storage) = session_storage() else {
+        return;
+    };
+
+    let _ = match job_id {
+        Some(job_id) => storage.set_item(JOB_STORAGE_KEY, job_id),
+        None => storage.remove_item(JOB_STORAGE_KEY)
+    };
+}
+
+#[derive(Clone, Copy)]
+struct Connection {
+    set_release_notes: WriteSignal<Option<ReleaseNotes>>,
+    set_preview: WriteSignal<String>,
     progress: ReadSignal<Option<Progress>>,
     set_progress: WriteSignal<Option<Progress>>,
-    set_error_message: WriteSignal<String>
-) -> Result<()> {
+    set_warnings: WriteSignal<Vec<String>>,
+    set_ticket_links: WriteSignal<Option<TicketLinkReport>>,
+    set_removed_commits: WriteSignal<Vec<RemovedCommit>>,
+    set_error_message: WriteSignal<String>,
+    set_web_socket: WriteSignal<Option<WebSocket>>
+}
+
+fn connect(request: ClientMessage, connection: Connection, attempt: u32) {
+    let Connection {
+        set_release_notes,
+        set_preview,
+        progress,
+        set_progress,
+        set_warnings,
+        set_ticket_links,
+        set_removed_commits,
+        set_error_message,
+        set_web_socket
+    } = connection;
+
+    // the job replays everything from the start, so whatever was shown before is rebuilt from scratch
+    if let ClientMessage::Attach { .. } = request {
+        set_progress(Some(Progress { stage: "Reconnecting".to_string(), ..Default::default() }));
+        set_preview(String::new());
+        set_warnings(vec![]);
+        set_ticket_links(None);
+        set_removed_commits(vec![]);
+    }
+
+    let web_socket = match WebSocket::new(&format!("ws://{}/submit", window().location().host().unwrap())) {
+        Ok(web_socket) => web_socket,
+        Err(error) => {
+            set_error_message(format!("{error:?}"));
+            set_progress(None);
+            return;
+        }
+    };
     web_socket.set_binary_type(web_sys::BinaryType::Arraybuffer);
 
     let ws = web_socket.clone();
     let on_open = Closure::<dyn FnMut()>::new(move || {
-        if let Err(error) = ws.send_with_str(&serde_json::to_string(&arguments).unwrap()) {
+        if let Err(error) = ws.send_with_str(&serde_json::to_string(&request).unwrap()) {
             set_error_message(format!("{error:?}"));
             let _ = ws.close();
             set_progress(None);
@@ -40,27 +139,67 @@ fn setup_callbacks(
     let ws = web_socket.clone();
     let on_message = Closure::<dyn FnMut(_)>::new(move |event: MessageEvent| {
         if let Ok(message) = event.data().dyn_into::<js_sys::JsString>() {
-            let message: String = message.into();
-
-            let token;
-            match serde_json::from_str::<Result<String, String>>(&message) {
-                Ok(Ok(new_token)) => token = new_token,
-                error => {
-                    if let Ok(Err(error_message)) = error {
-                        set_error_message(format!("Server error: {error_message}"));
-                    } else {
-                        set_error_message("Error parsing message.".to_string());
-                    }
+            let frame: String = message.into();
+
+            let envelope = match serde_json::from_str::<Envelope>(&frame) {
+                Ok(envelope) => envelope,
+                Err(_) => {
+                    set_error_message("Error parsing message.".to_string());
+                    store_job_id(None);
                     let _ = ws.close();
-                    set_progress(None);
                     return;
                 }
+            };
+
+            if envelope.version != PROTOCOL_VERSION {
+                set_error_message(format!("The server uses protocol version {}, expected {PROTOCOL_VERSION}.", envelope.version));
+                store_job_id(None);
+                let _ = ws.close();
+                return;
             }
-            match progress.get_untracked() {
-                Some(Progress::Cloning) => set_progress(Some(Progress::Streaming)),
-                Some(Progress::Streaming) => set_release_notes.update(|release_notes|
-                    *release_notes += &token),
-                None => {
+
+            // messages that arrive after cancelling leave the progress untouched
+            match envelope.message {
+                ServerMessage::Job { id } => store_job_id(Some(&id)),
+                ServerMessage::Progress { stage, percent } => set_progress.update(|progress| {
+                    if let Some(progress) = progress {
+                        progress.stage = stage;
+                        progress.percent = percent;
+                        progress.transfer = None;
+                    }
+                }),
+                ServerMessage::Transfer { progress: transfer } => set_progress.update(|progress| {
+                    if let Some(progress) = progress {
+                        progress.transfer = Some(transfer);
+                    }
+                }),
+                ServerMessage::Cleanup { removed } => set_removed_commits(removed),
+                ServerMessage::CommitsFound { count } => set_progress.update(|progress| {
+                    if let Some(progress) = progress {
+                        progress.commits = Some(count);
+                    }
+                }),
+                ServerMessage::Token { token } => {
+                    set_progress.update(|progress| {
+                        if let Some(progress) = progress {
+                            progress.tokens += 1;
+                        }
+                    });
+                    set_preview.update(|preview| preview.push_str(&token));
+                },
+                ServerMessage::TicketLinks { report } => set_ticket_links(Some(report)),
+                ServerMessage::Warning { message } => set_warnings.update(|warnings| warnings.push(message)),
+                ServerMessage::Notes { release_notes } => {
+                    set_preview(String::new());
+                    set_release_notes(Some(release_notes));
+                },
+                ServerMessage::Error { kind, message } => {
+                    set_error_message(format!("{}: {message}", error_label(kind)));
+                    store_job_id(None);
+                    let _ = ws.close();
+                },
+                ServerMessage::Done { .. } => {
+                    store_job_id(None);
                     let _ = ws.close();
                 }
             }
@@ -71,32 +210,72 @@ fn setup_callbacks(
     web_socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
     on_message.forget();
 
-    let ws = web_socket.clone();
-    let on_error = Closure::<dyn FnMut(_)>::new(move |error: ErrorEvent| {
-        set_error_message(error.message());
-        let _ = ws.close();
-        set_progress(None);
+    // a failed connection is closed right after, which is where it's retried
+    let on_error = Closure::<dyn FnMut()>::new(move || {
+        if stored_job_id().is_none() {
+            set_error_message("Unable to connect to the server.".to_string());
+        }
     });
     web_socket.set_onerror(Some(on_error.as_ref().unchecked_ref()));
     on_error.forget();
 
+    // the job is only forgotten once it has finished or been cancelled, so a job that is still stored means the
+    // connection was lost while it was running
     let on_close = Closure::<dyn FnMut()>::new(move || {
-        set_progress(None);
+        match stored_job_id() {
+            Some(job_id) if progress.get_untracked().is_some() && attempt < MAX_RECONNECT_ATTEMPTS => {
+                set_progress.update(|progress| {
+                    if let Some(progress) = progress {
+                        progress.stage = "Reconnecting".to_string();
+                    }
+                });
+                set_timeout(move || {
+                    // cancelled while waiting
+                    if progress.get_untracked().is_some() {
+                        connect(ClientMessage::Attach { job_id }, connection, attempt + 1);
+                    }
+                }, RECONNECT_DELAY);
+            },
+            Some(_) if progress.get_untracked().is_some() => {
+                set_error_message("Lost the connection to the server.".to_string());
+                set_progress(None);
+            },
+            _ => set_progress(None)
+        }
     });
     web_socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
     on_close.forget();
 
-    return Ok(());
+    set_web_socket(Some(web_socket));
+}
+
+// pathspecs are entered separated by commas
+fn split_pathspecs(pathspecs: &str) -> Vec<String> {
+    return pathspecs
+        .split(',')
+        .map(|pathspec| pathspec.trim().to_string())
+        .filter(|pathspec| !pathspec.is_empty())
+        .collect();
 }
 
 #[component]
-pub fn Form(default_arguments: Arguments, set_release_notes: WriteSignal<String>) -> impl IntoView {
-    let (repo_link, set_repo_link) = create_signal(default_arguments.repo_link);
+pub fn Form(
+    default_arguments: Arguments,
+    set_release_notes: WriteSignal<Option<ReleaseNotes>>,
+    set_preview: WriteSignal<String>
+) -> impl IntoView {
+    let (repo_link, set_repo_link) = create_signal(default_arguments.repo_link.clone());
+    // only updated once the link has been entered, since listing tags requires a clone
+    let (tags_repo_link, set_tags_repo_link) = create_signal(default_arguments.repo_link);
     let (product_name, set_product_name) = create_signal(default_arguments.product_name);
     let (release_tag, set_release_tag) = create_signal(default_arguments.release_tag);
     let (prev_release_tag, set_prev_release_tag) = create_signal(default_arguments.prev_release_tag);
     let (release_date, set_release_date) = create_signal(default_arguments.release_date);
     let (target_audience, set_target_audience) = create_signal(default_arguments.target_audience);
+    let (include_paths, set_include_paths) = create_signal(default_arguments.include_paths.join(", "));
+    let (exclude_paths, set_exclude_paths) = create_signal(default_arguments.exclude_paths.join(", "));
+    let (credit_contributors, set_credit_contributors) = create_signal(default_arguments.credit_contributors);
+    let (include_notes, set_include_notes) = create_signal(default_arguments.include_notes);
     let mut counter = default_arguments.tickets.len();
     let (tickets, set_tickets) = create_signal(default_arguments
         .tickets
@@ -107,11 +286,66 @@ pub fn Form(default_arguments: Arguments, set_release_notes: WriteSignal<String>
         .collect::<Vec<_>>()
     );
     let (progress, set_progress) = create_signal(None::<Progress>);
+    let (warnings, set_warnings) = create_signal(Vec::<String>::new());
+    let (ticket_links, set_ticket_links) = create_signal(None::<TicketLinkReport>);
+    let (removed_commits, set_removed_commits) = create_signal(Vec::<RemovedCommit>::new());
     let (web_socket, set_web_socket) = create_signal(None::<WebSocket>);
     let (error_message, set_error_message) = create_signal("".to_string());
+    let connection = Connection {
+        set_release_notes,
+        set_preview,
+        progress,
+        set_progress,
+        set_warnings,
+        set_ticket_links,
+        set_removed_commits,
+        set_error_message,
+        set_web_socket
+    };
+
+    // effects only run in the browser, where a job left over from before a reload is resumed
+    create_effect(move |_| {
+        if let Some(job_id) = stored_job_id() {
+            connect(ClientMessage::Attach { job_id }, connection, 0);
+        }
+    });
+
+    let tags_resource = create_local_resource(tags_repo_link, |repo_link| async move {
+        if repo_link.trim().is_empty() {
+            return Ok(vec![]);
+        }
+
+        list_tags(repo_link).await
+    });
+    let tags = Signal::derive(move || tags_resource
+        .get()
+        .and_then(|tags| tags.ok())
+        .unwrap_or_default());
+    let tags_status = move || {
+        if tags_resource.loading().get() {
+            return "Loading tags...".to_string();
+        }
+
+        match tags_resource.get() {
+            Some(Err(error)) => format!("Unable to list tags: {error}"),
+            Some(Ok(tags)) if !tags.is_empty() => format!("{} tags found", tags.len()),
+            _ => "".to_string()
+        }
+    };
+
+    let on_pick_release_tag = move |tag: String| {
+        // the previous release is pre-selected, but can still be changed afterwards
+        if let Some(prev_tag) = previous_tag(&tags.get_untracked(), &tag) {
+            set_prev_release_tag(prev_tag.name.clone());
+        }
+        set_release_tag(tag);
+    };
 
     let on_submit = move |_| {
         set_error_message("".to_string());
+        set_warnings(vec![]);
+        set_ticket_links(None);
+        set_removed_commits(vec![]);
 
         let arguments = Arguments {
             repo_link: repo_link(),
@@ -124,31 +358,20 @@ pub fn Form(default_arguments: Arguments, set_release_notes: WriteSignal<String>
                 .iter()
                 .map(|(_, (ticket, _))| ticket())
                 .collect::<Vec<Ticket>>(),
+            include_paths: split_pathspecs(&include_paths()),
+            exclude_paths: split_pathspecs(&exclude_paths()),
+            credit_contributors: credit_contributors(),
+            include_notes: include_notes()
         };
 
         if arguments.any_field_empty() {
             set_error_message("A field has been left empty.".to_string());
         }
 
-        set_progress(Some(Progress::Cloning));
-        set_release_notes("".to_string());
-
-        let mut web_socket;
-        match WebSocket::new(&format!("ws://{}/submit", window().location().host().unwrap())) {
-            Ok(websocket) => web_socket = websocket,
-            Err(error) => {
-                set_error_message(format!("{error:?}"));
-                set_progress(None);
-                return;
-            }
-        }
-
-        if let Err(error) = setup_callbacks(&mut web_socket, arguments, set_release_notes, progress, set_progress, set_error_message) {
-            set_error_message(format!("{error:?}"));
-            return;
-        }
-
-        set_web_socket(Some(web_socket));
+        set_progress(Some(Progress { stage: "Connecting".to_string(), ..Default::default() }));
+        set_release_notes(None);
+        set_preview(String::new());
+        connect(ClientMessage::Start { arguments }, connection, 0);
     };
 
     let add_ticket = move |_| {
@@ -160,12 +383,16 @@ pub fn Form(default_arguments: Arguments, set_release_notes: WriteSignal<String>
         <div class="px-[2vw] py-[6vh]">
             <div class="grid grid-cols-[repeat(2,max-content)] gap-4 mb-[3vh]">
                 <p>"Repository link:"</p>
-                <input
-                    class="w-[25em] px-[3px] text-[1rem] placeholder-gray-500 bg-gray-200 border-2 border-black"
-                    type="text"
-                    value={repo_link}
-                    on:input = move |event| set_repo_link(event_target_value(&event))
-                    placeholder = "https://github.com/example/example.git" />
+                <div>
+                    <input
+                        class="w-[25em] px-[3px] text-[1rem] placeholder-gray-500 bg-gray-200 border-2 border-black"
+                        type="text"
+                        value={repo_link}
+                        on:input = move |event| set_repo_link(event_target_value(&event))
+                        on:change = move |event| set_tags_repo_link(event_target_value(&event))
+                        placeholder = "https://github.com/example/example.git" />
+                    <p class="text-[0.8rem] text-gray-600">{tags_status}</p>
+                </div>
                 <p>"Product name:"</p>
                 <input
                     class="w-[10em] px-[3px] text-[1rem] bg-gray-200 border-2 border-black"
@@ -173,17 +400,9 @@ pub fn Form(default_arguments: Arguments, set_release_notes: WriteSignal<String>
                     value={product_name}
                     on:input = move |event| set_product_name(event_target_value(&event)) />
                 <p>"Release tag:"</p>
-                <input
-                    class="w-[10em] px-[3px] text-[1rem] bg-gray-200 border-2 border-black"
-                    type="text"
-                    value={release_tag}
-                    on:input = move |event| set_release_tag(event_target_value(&event)) />
+                <TagPicker list_id="release-tags" tag=release_tag on_pick=on_pick_release_tag tags />
                 <p>"Previous release tag:"</p>
-                <input
-                    class="w-[10em] px-[3px] text-[1rem] bg-gray-200 border-2 border-black"
-                    type="text"
-                    value={prev_release_tag}
-                    on:input = move |event| set_prev_release_tag(event_target_value(&event)) />
+                <TagPicker list_id="prev-release-tags" tag=prev_release_tag on_pick=set_prev_release_tag tags />
                 <p>"Release date:"</p>
                 <input
                     class="w-[10em] px-[3px] text-[1rem] bg-gray-200 border-2 border-black"
@@ -222,6 +441,33 @@ pub fn Form(default_arguments: Arguments, set_release_notes: WriteSignal<String>
                         selected=move || target_audience() == TargetAudience::Technical
                     >"Technical"</option>
                 </select>
+                // for notes on one component of a monorepo
+                <p>"Paths:"</p>
+                <input
+                    class="w-[25em] px-[3px] text-[1rem] placeholder-gray-500 bg-gray-200 border-2 border-black"
+                    type="text"
+                    value={include_paths}
+                    on:input = move |event| set_include_paths(event_target_value(&event))
+                    placeholder = "All paths, or e.g. services/billing/**, libs/payments" />
+                <p>"Excluded paths:"</p>
+                <input
+                    class="w-[25em] px-[3px] text-[1rem] placeholder-gray-500 bg-gray-200 border-2 border-black"
+                    type="text"
+                    value={exclude_paths}
+                    on:input = move |event| set_exclude_paths(event_target_value(&event))
+                    placeholder = "e.g. services/billing/tests" />
+                <p>"Credit contributors:"</p>
+                <input
+                    class="w-[1em] self-center"
+                    type="checkbox"
+                    checked=credit_contributors
+                    on:change = move |event| set_credit_contributors(event_target_checked(&event)) />
+                <p>"Include git notes:"</p>
+                <input
+                    class="w-[1em] self-center"
+                    type="checkbox"
+                    checked=include_notes
+                    on:change = move |event| set_include_notes(event_target_checked(&event)) />
             </div>
             <h1 class="text-[1.2em] underline">"Tickets"</h1>
             <div
@@ -243,8 +489,15 @@ pub fn Form(default_arguments: Arguments, set_release_notes: WriteSignal<String>
             <div class="flex mt-[2vh]">
                 <p
                     class="pr-[0.5em] py-[0.2em]"
-                    style:display=move || progress().is_none().then(|| "None")
-                >{move || progress().map(|progress| format!("{progress:?}"))}</p>
+                    style:display=move || progress().is_none().then_some("None")
+                >{move || progress().map(|progress| progress.label())}</p>
+                // clones of large repositories take minutes, the bar shows they haven't stalled
+                <progress
+                    class="w-[10em] mr-[0.5em] self-center"
+                    style:display=move || progress().and_then(|progress| progress.transfer).is_none().then_some("None")
+                    max=move || progress().and_then(|progress| progress.transfer).map_or(1, |transfer| transfer.phase().2.max(1))
+                    value=move || progress().and_then(|progress| progress.transfer).map_or(0, |transfer| transfer.phase().1)
+                ></progress>
                 <button
                     class="px-[0.5em] py-[0.2em] border-2 border-black hover:bg-gray-200"
                     style:display=move || progress().map(|_| "None")
@@ -252,17 +505,56 @@ pub fn Form(default_arguments: Arguments, set_release_notes: WriteSignal<String>
                 >"Submit"</button>
                 <button
                     class="px-[0.5em] py-[0.2em] border-2 border-black hover:bg-gray-200"
-                    style:display=move || progress().is_none().then(|| "None")
+                    style:display=move || progress().is_none().then_some("None")
                     on:click=move |_| {
-                        web_socket.get_untracked().map(|web_socket|
-                            web_socket.close());
+                        store_job_id(None);
+                        // the server stops the job for good instead of waiting for a reconnect
+                        web_socket.get_untracked().map(|web_socket| {
+                            let _ = web_socket.send_with_str(&serde_json::to_string(&ClientMessage::Cancel).unwrap());
+                            web_socket.close()
+                        });
                         set_web_socket(None);
                     }
                 >"Cancel"</button>
             </div>
+            // reverted, fixed up and already shipped commits, which the notes leave out
+            <details
+                class="text-gray-600"
+                style:display=move || removed_commits().is_empty().then_some("None")
+            >
+                <summary>{move || format!("{} commits left out", removed_commits().len())}</summary>
+                <ul class="ml-[1.5em] list-disc">
+                    {move || removed_commits().into_iter().map(|commit| view! {
+                        <li>{format!("{} {}: {}", commit.id.get(..7).unwrap_or(&commit.id), commit.subject, commit.describe())}</li>
+                    }).collect_view()}
+                </ul>
+            </details>
+            // what the ticket ids in commit messages couldn't connect, usually missing ids on either side
+            {move || ticket_links().map(|report| {
+                let unlinked_commits = report.unlinked_commits.len();
+                view! {
+                    <details
+                        class="text-amber-600"
+                        style:display=(unlinked_commits == 0).then_some("None")
+                    >
+                        <summary>{format!("{unlinked_commits} commits reference no ticket")}</summary>
+                        <ul class="ml-[1.5em] list-disc">
+                            {report.unlinked_commits.into_iter().map(|commit| view! { <li>{commit}</li> }).collect_view()}
+                        </ul>
+                    </details>
+                    {report.unlinked_tickets.into_iter().map(|ticket| view! {
+                        <p class="text-amber-600">{format!("No commit references the ticket {ticket}")}</p>
+                    }).collect_view()}
+                }
+            })}
+            <For
+                each=warnings
+                key=|warning| warning.clone()
+                children=move |warning| view! { <p class="text-amber-600">{warning}</p> }
+            />
             <p
                 class="text-red-600"
-                style:display=move || error_message().is_empty().then(|| "None")
+                style:display=move || error_message().is_empty().then_some("None")
             >{error_message}</p>
         </div>
     }
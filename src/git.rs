@@ -1,54 +1,1267 @@
 use anyhow::{anyhow, Result};
-use std::cmp::Ordering;
-use std::hash::{DefaultHasher, Hash, Hasher};
-use git2::Repository;
-
-pub fn fetch_or_clone(repo_link: String) -> Result<Repository> {
-    // the repo_link is hashed and the repo is cloned into ./repos/<hash> rather than ./repos/<repo-name>
-    // this is because different repos may have the same name
-    let mut hasher = DefaultHasher::new();
-    repo_link.hash(&mut hasher);
-    let hash = hasher.finish();
-    let repo_path = format!("./repos/{hash}");
-    let repo_path = std::path::Path::new(&repo_path);
-
-    if repo_path.exists() {
-        let repo = Repository::open(repo_path)?;
-        let mut remote = repo.find_remote("origin")?;
-        remote.fetch::<&str>(&[], None, None)?;         // fetch from origin
+use std::collections::HashSet;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::cell::Cell;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+use git2::build::RepoBuilder;
+use git2::{
+    Commit, Diff, DiffDelta, ErrorCode, FetchOptions, FetchPrune, FileMode, Mailmap, ObjectType, Oid, Patch, Pathspec,
+    PathspecFlags, RemoteRedirect, Repository, Signature, Sort
+};
+
+use crate::cache::{self, RepoCache, RepoGuard};
+use crate::cancel::{CancelToken, Cancelled};
+use crate::config::{self, GitConfig};
+use crate::contributors::{co_authors, BotFilter};
+use crate::credentials::Credentials;
+use crate::sandbox::{RepoUrl, TransferLimits};
+use crate::util::{Contributor, RemovalReason, RemovedCommit, TagInfo, TransferProgress};
+
+// libgit2 reports progress every few objects, it is passed on at most this often
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+// cached repos are bare and mirror the remote's branches, tags and notes, since only commit metadata is read from them
+const FETCH_REFSPECS: [&str; 3] = ["+refs/heads/*:refs/heads/*", "+refs/tags/*:refs/tags/*", NOTES_REFSPEC];
+const NOTES_REFSPEC: &str = "+refs/notes/*:refs/notes/*";
+
+// libgit2 refuses to open repos with extensions it doesn't know, and partial clones set extensions.partialclone.
+// it reads whatever objects are present just fine, see fetch_missing for the rest. called by config::init, since
+// the option must not change while libgit2 is in use
+pub fn allow_partial_clones() -> Result<()> {
+    static ALLOWED: OnceLock<Result<(), String>> = OnceLock::new();
+    return ALLOWED
+        .get_or_init(|| unsafe { git2::opts::set_extensions(&["partialclone"]) }.map_err(|error| error.message().to_string()))
+        .clone()
+        .map_err(|error| anyhow!("Unable to allow partial clones: {error}"));
+}
+
+// repos cloned with a filter fetch the objects left out from their promisor remote
+fn is_partial(repo: &Repository) -> bool {
+    return repo.config()
+        .and_then(|config| config.get_bool("remote.origin.promisor"))
+        .unwrap_or(false);
+}
+
+// parses what git prints with --progress, such as "Receiving objects:  45% (450/1000), 1.20 MiB | 2.00 MiB/s",
+// returns whether the line was progress
+fn parse_progress(line: &str, progress: &mut TransferProgress) -> bool {
+    let counts = |rest: &str| -> Option<(usize, usize)> {
+        let (_, counts) = rest.split_once('(')?;
+        let (done, total) = counts.split_once(')')?.0.split_once('/')?;
+        return Some((done.trim().parse().ok()?, total.trim().parse().ok()?));
+    };
+
+    if let Some(rest) = line.strip_prefix("Receiving objects:") {
+        if let Some((received, total)) = counts(rest) {
+            // objects are indexed as they arrive
+            progress.received_objects = received;
+            progress.indexed_objects = received;
+            progress.total_objects = total;
+        }
+    } else if let Some(rest) = line.strip_prefix("Resolving deltas:") {
+        if let Some((indexed, total)) = counts(rest) {
+            progress.indexed_deltas = indexed;
+            progress.total_deltas = total;
+        }
     } else {
-        Repository::clone(&repo_link, repo_path)?;
+        // the remote's own counters, its errors start with remote: as well
+        return line.strip_prefix("remote:").is_some_and(|rest| {
+            let rest = rest.trim_start();
+            rest.contains('%') || rest.starts_with("Enumerating objects") || rest.starts_with("Total")
+        });
+    }
+
+    return true;
+}
+
+// what every run of the git executable for a remote needs
+struct RemoteContext<'a> {
+    git_config: &'a GitConfig,
+    credentials: &'a Credentials,
+    url: &'a RepoUrl,
+    cancel: &'a CancelToken
+}
+
+// runs the git executable, which is only needed for partial clones, with the same limits as libgit2 transfers.
+// the size is measured by how much watched grows, since the executable doesn't report what it received
+fn run_git(
+    context: &RemoteContext,
+    args: &[&str],
+    watched: &Path,
+    stdin: Option<String>,
+    on_progress: &dyn Fn(TransferProgress)
+) -> Result<()> {
+    let RemoteContext { git_config, credentials, url, cancel } = *context;
+    let limits = TransferLimits::new(git_config);
+    let initial_size = cache::dir_size(watched);
+
+    // the target of a redirect wouldn't have been checked against the policy
+    let mut config = vec![("http.followRedirects".to_string(), "false".to_string())];
+    config.extend(credentials.git_config(url.host.as_deref()));
+    let mut command = Command::new(&git_config.executable);
+    command
+        .args(args)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("GIT_CONFIG_COUNT", config.len().to_string())
+        .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    for (index, (key, value)) in config.iter().enumerate() {
+        command.env(format!("GIT_CONFIG_KEY_{index}"), key).env(format!("GIT_CONFIG_VALUE_{index}"), value);
+    }
+
+    let mut child = command
+        .spawn()
+        .map_err(|error| anyhow!(
+            "Unable to run git at {}, which git.partial_clone needs: {error}",
+            git_config.executable.display()))?;
+    // read on another thread, so a chatty git can't block on a full pipe. progress lines end with \r so they can
+    // overwrite each other on a terminal, everything else is kept for the error message
+    let stderr = child.stderr.take();
+    let progress = Arc::new(Mutex::new(TransferProgress::default()));
+    let parsed = progress.clone();
+    let stderr = std::thread::spawn(move || {
+        let Some(stderr) = stderr else {
+            return String::new();
+        };
+
+        let mut messages = vec![];
+        let mut line = vec![];
+        for byte in BufReader::new(stderr).bytes().map_while(Result::ok).chain(*b"\n") {
+            if byte != b'\r' && byte != b'\n' {
+                line.push(byte);
+                continue;
+            }
+
+            let text = String::from_utf8_lossy(&line).trim().to_string();
+            line.clear();
+            if !text.is_empty() && !parse_progress(&text, &mut parsed.lock().unwrap()) {
+                messages.push(text);
+            }
+        }
+        messages.join("\n")
+    });
+    if let (Some(input), Some(mut child_stdin)) = (stdin, child.stdin.take()) {
+        child_stdin.write_all(input.as_bytes())?;
+    }
+
+    let mut reported = TransferProgress::default();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if cancel.is_cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(Cancelled.into());
+        }
+        let received_bytes = cache::dir_size(watched).saturating_sub(initial_size);
+        if !limits.allow(received_bytes as usize) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(limits.exceeded().map(anyhow::Error::from).unwrap_or_else(|| anyhow!("git was stopped.")));
+        }
+
+        let current = TransferProgress { received_bytes: received_bytes as usize, ..*progress.lock().unwrap() };
+        if current != reported {
+            on_progress(current);
+            reported = current;
+        }
+        std::thread::sleep(PROGRESS_INTERVAL);
+    };
+
+    let stderr = stderr.join().unwrap_or_default();
+    if !status.success() {
+        // the output may quote the remote url, which can carry credentials
+        return Err(anyhow!(credentials.redact(stderr.trim())));
+    }
+
+    return Ok(());
+}
+
+// reports the progress of the transfer through on_progress, but not when another request already fetched the repo.
+// the transfer is aborted once cancel is, failing with Cancelled. the repo is kept in the cache until the guard is dropped
+pub fn fetch_or_clone<F>(
+    repo_link: String,
+    credentials: &Credentials,
+    on_progress: F,
+    cancel: &CancelToken
+) -> Result<(Repository, RepoGuard<'static>)>
+where F: Fn(TransferProgress) {
+    let git_config = &config::get().git;
+    let url = RepoUrl::parse(&repo_link)?;
+    url.check(git_config)?;
+
+    // the repo is cloned into <repos.path>/<digest of the normalized link> rather than <repos.path>/<repo-name>
+    // this is because different repos may have the same name, and the same repo may be linked to in several ways
+    let cache = RepoCache::global();
+    let repo_path = cache.dir(&url);
+    let repo_path = repo_path.as_path();
+
+    // requests for the same repo wait for the one fetching it, and use its result if it finished after they came in
+    let requested_at = Instant::now();
+    let lock = cache.lock(&url);
+    let mut fetched_at = lock.lock().unwrap_or_else(PoisonError::into_inner);
+    if fetched_at.is_some_and(|fetched_at| fetched_at >= requested_at) {
+        cache.record_use(&url)?;
+        let guard = cache.read(&url);
+        return Ok((Repository::open(repo_path)?, guard));
+    }
+    // requests still reading the repo would see it change under them
+    cache.wait_for_readers(&url);
+    // cancelled while waiting for the lock or the readers
+    if cancel.is_cancelled() {
+        return Err(Cancelled.into());
+    }
+
+    let limits = TransferLimits::new(git_config);
+    let mut callbacks = credentials.remote_callbacks();
+    let reported_at = Cell::new(None::<Instant>);
+    callbacks.transfer_progress(|progress| {
+        if reported_at.get().is_none_or(|reported_at| reported_at.elapsed() >= PROGRESS_INTERVAL) {
+            on_progress(TransferProgress {
+                received_objects: progress.received_objects(),
+                indexed_objects: progress.indexed_objects(),
+                total_objects: progress.total_objects(),
+                received_bytes: progress.received_bytes(),
+                indexed_deltas: progress.indexed_deltas(),
+                total_deltas: progress.total_deltas()
+            });
+            reported_at.set(Some(Instant::now()));
+        }
+        return limits.allow(progress.received_bytes()) && !cancel.is_cancelled();
+    });
+    callbacks.sideband_progress(|_| limits.allow(0) && !cancel.is_cancelled());
+    let context = RemoteContext { git_config, credentials, url: &url, cancel };
+    let mut fetch_options = FetchOptions::new();
+    fetch_options.remote_callbacks(callbacks);
+    fetch_options.follow_redirects(RemoteRedirect::None);
+    // branches and tags deleted on the remote are deleted from the mirror as well
+    fetch_options.prune(FetchPrune::On);
+
+    // libgit2 errors may quote the remote url, which can carry credentials
+    let fail = |error: git2::Error| match limits.exceeded() {
+        Some(refusal) => anyhow::Error::from(refusal),
+        None if cancel.is_cancelled() => anyhow::Error::from(Cancelled),
+        None => anyhow!(credentials.redact(error.message()))
+    };
+    // a directory that isn't a bare repo with an origin is what's left of a clone that was interrupted,
+    // or a clone with a working tree made by an older version
+    let origin = Repository::open(repo_path)
+        .ok()
+        .filter(|repo| repo.is_bare() && repo.find_remote("origin").is_ok());
+    if let Some(repo) = origin {
+        // mirrors made by older versions may have kept credentials from the link they were cloned with
+        repo.remote_set_url("origin", &repo_link)?;
+        // mirrors made by older versions left the notes out
+        let refspecs = repo.find_remote("origin")?.fetch_refspecs()?;
+        if !refspecs.iter().flatten().any(|refspec| refspec == NOTES_REFSPEC) {
+            repo.remote_add_fetch("origin", NOTES_REFSPEC)?;
+        }
+
+        if is_partial(&repo) {
+            // the filter the repo was cloned with is kept in its config and applies to the fetch
+            let repo_dir = repo_path.to_string_lossy();
+            let args = ["-C", &repo_dir, "fetch", "--progress", "--prune", "origin"];
+            run_git(&context, &args, repo_path, None, &on_progress)?;
+        } else {
+            let mut remote = repo.find_remote("origin")?;
+            remote.fetch::<&str>(&[], Some(&mut fetch_options), None).map_err(fail)?;         // fetch from origin
+        }
+    } else {
+        if repo_path.exists() {
+            std::fs::remove_dir_all(repo_path)?;
+        }
+
+        // renamed once complete, so a crash can't leave a partial clone where the repo is expected
+        let clone_path = cache.clone_dir(&url);
+        if clone_path.exists() {
+            std::fs::remove_dir_all(&clone_path)?;
+        }
+        let cloned = match git_config.partial_clone.filter() {
+            // remotes that don't support the filter send everything, git only warns about it
+            Some(filter) => {
+                std::fs::create_dir_all(&clone_path)?;
+                let clone_dir = clone_path.to_string_lossy();
+                let args = ["clone", "--bare", "--progress", "--filter", filter, "--", &repo_link, &clone_dir];
+                run_git(&context, &args, &clone_path, None, &on_progress).and_then(|_| {
+                    // a bare clone fetches branches and tags, but leaves no refspecs for later fetches
+                    let repo = Repository::open(&clone_path)?;
+                    for refspec in FETCH_REFSPECS {
+                        repo.remote_add_fetch("origin", refspec)?;
+                    }
+                    // and no notes, which cost another round trip. the filter applies to them as well
+                    let args = ["-C", &clone_dir, "fetch", "--progress", "origin", NOTES_REFSPEC];
+                    return run_git(&context, &args, &clone_path, None, &on_progress);
+                })
+            },
+            None => RepoBuilder::new()
+                .bare(true)
+                .remote_create(|repo, name, url| {
+                    repo.remote_with_fetch(name, url, FETCH_REFSPECS[0])?;
+                    for refspec in &FETCH_REFSPECS[1..] {
+                        repo.remote_add_fetch(name, refspec)?;
+                    }
+                    return repo.find_remote(name);
+                })
+                .fetch_options(fetch_options)
+                .clone(&repo_link, &clone_path)
+                .map(|_| ())
+                .map_err(fail)
+        };
+        if let Err(error) = cloned {
+            let _ = std::fs::remove_dir_all(&clone_path);
+            return Err(error);
+        }
+        std::fs::rename(&clone_path, repo_path)?;
+    }
+
+    *fetched_at = Some(Instant::now());
+    cache.record_use(&url)?;
+    let guard = cache.read(&url);
+
+    return Ok((Repository::open(repo_path)?, guard));
+}
+
+// partial clones only have what their filter let through, so anything reading file contents or, for treeless
+// clones, trees has to fetch them first. objects that are present are skipped, and so are full clones
+pub fn fetch_missing(repo: &Repository, oids: &[Oid], credentials: &Credentials, cancel: &CancelToken) -> Result<()> {
+    if !is_partial(repo) {
+        return Ok(());
+    }
+
+    let odb = repo.odb()?;
+    let missing = oids
+        .iter()
+        .filter(|oid| !odb.exists(**oid))
+        .map(|oid| format!("{oid}\n"))
+        .collect::<String>();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let git_config = &config::get().git;
+    let remote = repo.find_remote("origin")?;
+    let url = RepoUrl::parse(remote.url().unwrap_or_default())?;
+    url.check(git_config)?;
+
+    // the same fetch git runs when it lazily fetches missing objects itself, wanted trees come with their subtrees
+    let repo_path = repo.path().to_string_lossy();
+    let args = [
+        "-C", &repo_path,
+        "-c", "fetch.negotiationAlgorithm=noop",
+        "fetch", "--quiet", "--no-tags", "--no-write-fetch-head", "--recurse-submodules=no",
+        "--filter=blob:none", "--stdin", "origin"
+    ];
+    let context = RemoteContext { git_config, credentials, url: &url, cancel };
+    return run_git(&context, &args, repo.path(), Some(missing), &|_| {});
+}
+
+pub fn list_tags(repo: &Repository) -> Result<Vec<TagInfo>> {
+    let mut tags = vec![];
+    for name in repo.tag_names(None)?.iter().flatten() {
+        let reference = repo.find_reference(&format!("refs/tags/{name}"))?;
+        // tags pointing at trees or blobs can't be release tags
+        let Ok(commit) = reference.peel_to_commit() else {
+            continue;
+        };
+
+        let tagger_time = reference
+            .peel(ObjectType::Tag)
+            .ok()
+            .and_then(|tag| tag.into_tag().ok())
+            .and_then(|tag| tag.tagger().map(|tagger| tagger.when()));
+        let time = tagger_time.unwrap_or(commit.time());
+
+        tags.push(TagInfo {
+            name: name.to_string(),
+            date: chrono::DateTime::from_timestamp(time.seconds(), 0),
+            target: commit.id().to_string()
+        });
+    }
+
+    return Ok(tags);
+}
+
+// the id of the commit a tag or branch points to
+pub fn resolve_commit(repo: &Repository, name: &str) -> Result<String> {
+    let reference = repo.resolve_reference_from_short_name(name.trim())?;
+    return Ok(reference.peel_to_commit()?.id().to_string());
+}
+
+// a top-level directory or file of the repo and the lines a commit changed in it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PathStat {
+    pub path: String,           // directories end with a slash
+    pub insertions: usize,
+    pub deletions: usize
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DiffStat {
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+    pub paths: Vec<PathStat>    // most lines changed first
+}
+
+#[derive(Clone, Debug)]
+pub struct CommitInfo {
+    pub id: String,
+    pub author: String,
+    pub author_email: String,
+    pub date: chrono::DateTime<chrono::Utc>,
+    pub message: String,
+    pub diffstat: DiffStat      // empty for merge commits, like `git log --stat`
+}
+
+// the first component of the path, "src/" for src/main.rs and "README.md" for README.md
+fn top_level_path(path: &Path) -> String {
+    let mut components = path.components();
+    let first = components.next().map(|component| component.as_os_str().to_string_lossy().to_string()).unwrap_or_default();
+    return match components.next() {
+        Some(_) => format!("{first}/"),
+        None => first
+    };
+}
+
+// the paths a release covers, like `git log -- <include> ':!<exclude>'`. pathspecs match a directory with everything
+// in it, such as services/billing, or use wildcards, such as services/billing/** or *.md
+pub struct PathFilter {
+    include: Option<Pathspec>,
+    exclude: Option<Pathspec>
+}
+
+impl PathFilter {
+    // every path is included without include pathspecs
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        let pathspec = |pathspecs: &[String]| -> Result<Option<Pathspec>> {
+            let pathspecs = pathspecs
+                .iter()
+                .map(|pathspec| pathspec.trim())
+                .filter(|pathspec| !pathspec.is_empty())
+                .collect::<Vec<_>>();
+            if pathspecs.is_empty() {
+                return Ok(None);
+            }
+            return Pathspec::new(pathspecs)
+                .map(Some)
+                .map_err(|error| anyhow!("Invalid pathspec: {}", error.message()));
+        };
+
+        return Ok(PathFilter { include: pathspec(include)?, exclude: pathspec(exclude)? });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.include.is_none() && self.exclude.is_none();
     }
 
-    return Ok(Repository::open(repo_path)?);
+    pub fn matches(&self, path: &Path) -> bool {
+        let included = self.include
+            .as_ref()
+            .is_none_or(|include| include.matches_path(path, PathspecFlags::DEFAULT));
+        let excluded = self.exclude
+            .as_ref()
+            .is_some_and(|exclude| exclude.matches_path(path, PathspecFlags::DEFAULT));
+        return included && !excluded;
+    }
+
+    // files moved into or out of the paths count as changes to them
+    fn matches_delta(&self, delta: &DiffDelta) -> bool {
+        return [delta.old_file().path(), delta.new_file().path()]
+            .into_iter()
+            .flatten()
+            .any(|path| self.matches(path));
+    }
 }
 
-pub fn read_commit_messages(repo: &mut Repository, release_tag: &str, prev_release_tag: &str) -> Result<Vec<String>> {
-    let release_tag = repo.resolve_reference_from_short_name(release_tag.trim())?;
-    let prev_release_tag = repo.resolve_reference_from_short_name(prev_release_tag.trim())?;
+// only the files filter matches are counted
+fn diff_stat(diff: &Diff, filter: &PathFilter) -> Result<DiffStat> {
+    let mut diffstat = DiffStat::default();
+    for (index, delta) in diff.deltas().enumerate() {
+        if !filter.matches_delta(&delta) {
+            continue;
+        }
+
+        let (insertions, deletions) = match Patch::from_diff(diff, index)? {
+            Some(patch) => {
+                let (_, insertions, deletions) = patch.line_stats()?;
+                (insertions, deletions)
+            },
+            None => (0, 0)
+        };
+        diffstat.files_changed += 1;
+        diffstat.insertions += insertions;
+        diffstat.deletions += deletions;
 
-    let release_commit = release_tag.peel_to_commit()?;
-    let prev_release_commit = prev_release_tag.peel_to_commit()?;
-    if prev_release_commit.time().cmp(&release_commit.time()) != Ordering::Less {
-        return Err(anyhow!("prev_release_tag doesn't predate release_tag."));
+        let Some(path) = delta.new_file().path().or(delta.old_file().path()) else {
+            continue;
+        };
+        let path = top_level_path(path);
+        match diffstat.paths.iter_mut().find(|stat| stat.path == path) {
+            Some(stat) => {
+                stat.insertions += insertions;
+                stat.deletions += deletions;
+            },
+            None => diffstat.paths.push(PathStat { path, insertions, deletions })
+        }
     }
+    diffstat.paths.sort_by_key(|stat| std::cmp::Reverse(stat.insertions + stat.deletions));
+
+    return Ok(diffstat);
+}
 
+// commits reachable from include but not from exclude, newest first, like `git log exclude..include`
+fn walk(repo: &Repository, include: Oid, exclude: Oid) -> Result<Vec<Commit>> {
     // a revwalk denotes an iterator over commits
+    // it yields every commit reachable from include that isn't reachable from exclude, so side branches merged in
+    // between are included
     let mut revwalk = repo.revwalk()?;
-    revwalk.push(release_commit.id())?;         // initial feature commit
+    revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+    revwalk.push(include)?;
+    revwalk.hide(exclude)?;
+
+    let mut commits = vec![];
+    for commit_oid in revwalk {
+        let commit = repo.find_commit(commit_oid?)?;
+
+        if commit.message().is_some() {      // Commit::message will return None if the message is not valid utf-8
+            commits.push(commit);
+        }
+    }
+
+    return Ok(commits);
+}
+
+// the commits both tags point to, which must share some history for there to be a range between them
+fn release_range<'repo>(repo: &'repo Repository, release_tag: &str, prev_release_tag: &str) -> Result<(Commit<'repo>, Commit<'repo>)> {
+    let release_commit = repo.resolve_reference_from_short_name(release_tag.trim())?.peel_to_commit()?;
+    let prev_release_commit = repo.resolve_reference_from_short_name(prev_release_tag.trim())?.peel_to_commit()?;
+
+    // tags on unrelated histories have no merge-base, so there is no meaningful range between them
+    match repo.merge_base(release_commit.id(), prev_release_commit.id()) {
+        Ok(_) => {},
+        Err(error) if error.code() == ErrorCode::NotFound => {
+            return Err(anyhow!("release_tag and prev_release_tag share no common history."));
+        },
+        Err(error) => return Err(error.into())
+    }
+
+    return Ok((release_commit, prev_release_commit));
+}
+
+// compared to the first parent, root commits to the empty tree. the trees must be present, see fetch_trees
+fn parent_diff<'repo>(repo: &'repo Repository, commit: &Commit) -> Result<Diff<'repo>> {
+    let parent_tree = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None
+    };
+    return Ok(repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?);
+}
+
+// treeless clones have none of the trees, blobless clones none of the blobs. the deltas between the trees tell
+// which blobs are needed, so they are fetched in two rounds, first the trees of the commits and their parents
+fn fetch_trees<'a>(
+    repo: &Repository,
+    commits: impl Iterator<Item = &'a Commit<'a>>,
+    credentials: &Credentials,
+    cancel: &CancelToken
+) -> Result<()> {
+    let mut trees = vec![];
+    for commit in commits {
+        trees.push(commit.tree_id());
+        if let Ok(parent) = commit.parent(0) {
+            trees.push(parent.tree_id());
+        }
+    }
+
+    return fetch_missing(repo, &trees, credentials, cancel);
+}
+
+// then the blobs the diffs compare. submodules are recorded as commits of another repo
+fn fetch_blobs<'a>(
+    repo: &Repository,
+    diffs: impl Iterator<Item = &'a Diff<'a>>,
+    credentials: &Credentials,
+    cancel: &CancelToken
+) -> Result<()> {
+    let blobs = diffs
+        .flat_map(|diff| diff.deltas())
+        .flat_map(|delta| [delta.old_file(), delta.new_file()])
+        .filter(|file| !file.id().is_zero() && file.mode() != FileMode::Commit)
+        .map(|file| file.id())
+        .collect::<Vec<_>>();
+
+    return fetch_missing(repo, &blobs, credentials, cancel);
+}
+
+// like `git log prev_release_tag..release_tag --stat -- <filter>`. partial clones fetch the trees and blobs the diffs
+// need first, so this may transfer data and fails with Cancelled once cancel is
+pub fn read_commits(
+    repo: &Repository,
+    release_tag: &str,
+    prev_release_tag: &str,
+    filter: &PathFilter,
+    credentials: &Credentials,
+    cancel: &CancelToken
+) -> Result<Vec<CommitInfo>> {
+    let (release_commit, prev_release_commit) = release_range(repo, release_tag, prev_release_tag)?;
+    let commits = walk(repo, release_commit.id(), prev_release_commit.id())?;
+
+    // merges are compared to their first parent, and only to tell whether they brought in changes to the paths
+    let compared = |commit: &Commit| commit.parent_count() <= 1 || !filter.is_empty();
+    fetch_trees(repo, commits.iter().filter(|commit| compared(commit)), credentials, cancel)?;
+    let mut diffs = vec![];
+    for commit in commits {
+        if cancel.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+        if !compared(&commit) {
+            diffs.push((commit, None));
+            continue;
+        }
+
+        let diff = parent_diff(repo, &commit)?;
+        if !filter.is_empty() && !diff.deltas().any(|delta| filter.matches_delta(&delta)) {
+            continue;
+        }
+        // merges have no diffstat, like with `git log --stat`
+        match commit.parent_count() > 1 {
+            true => diffs.push((commit, None)),
+            false => diffs.push((commit, Some(diff)))
+        }
+    }
+
+    // renames are detected among all the files of a commit, so their blobs are needed even if the filter leaves them out
+    fetch_blobs(repo, diffs.iter().filter_map(|(_, diff)| diff.as_ref()), credentials, cancel)?;
+
+    let mut commit_infos = vec![];
+    for (commit, diff) in diffs {
+        if cancel.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+
+        let diffstat = match diff {
+            Some(mut diff) => {
+                // renamed files count as one file changed rather than one deleted and one added
+                diff.find_similar(None)?;
+                diff_stat(&diff, filter)?
+            },
+            None => DiffStat::default()
+        };
+        let author = commit.author();
+        commit_infos.push(CommitInfo {
+            id: commit.id().to_string(),
+            author: author.name().unwrap_or_default().to_string(),
+            author_email: author.email().unwrap_or_default().to_string(),
+            date: chrono::DateTime::from_timestamp(author.when().seconds(), 0).unwrap_or_default(),
+            message: commit.message().unwrap_or_default().to_string(),
+            diffstat
+        });
+    }
+
+    return Ok(commit_infos);
+}
+
+fn subject(message: &str) -> &str {
+    return message.lines().next().unwrap_or_default().trim();
+}
+
+// the commit a revert reverts, by the id git writes into its message or else by the quoted subject
+fn reverted<'a>(commit: &CommitInfo, older: impl Iterator<Item = &'a CommitInfo>) -> Option<&'a CommitInfo> {
+    let subject = subject(&commit.message);
+    let reverted_subject = subject.strip_prefix("Revert \"")?.strip_suffix('"')?;
+
+    let mut older = older;
+    let reverted_id = commit.message
+        .split_once("This reverts commit ")
+        .map(|(_, rest)| rest.chars().take_while(char::is_ascii_hexdigit).collect::<String>());
+    return match reverted_id {
+        Some(id) if id.len() >= 7 => older.find(|other| other.id.starts_with(&id)),
+        _ => older.find(|other| self::subject(&other.message) == reverted_subject)
+    };
+}
+
+// the commit a fixup! or squash! commit amends, by its subject or, as `git commit --fixup` allows, its id.
+// returns whether its message is to be kept as well
+fn fixed_up<'a>(commit: &CommitInfo, older: impl Iterator<Item = &'a CommitInfo>) -> Option<(&'a CommitInfo, bool)> {
+    let mut subject = subject(&commit.message);
+    let mut squash = false;
+    loop {
+        if let Some(rest) = subject.strip_prefix("fixup! ") {
+            subject = rest.trim_start();
+        } else if let Some(rest) = subject.strip_prefix("squash! ") {
+            subject = rest.trim_start();
+            squash = true;
+        } else {
+            break;
+        }
+    }
+    if subject == self::subject(&commit.message) {
+        return None;
+    }
+
+    let is_id = subject.len() >= 7 && subject.chars().all(|c| c.is_ascii_hexdigit());
+    let mut older = older;
+    return older
+        .find(|other| self::subject(&other.message) == subject || (is_id && other.id.starts_with(subject)))
+        .map(|target| (target, squash));
+}
+
+// the diffstat of a commit and of its fixups, files changed by several of them are counted more than once
+fn merge_diffstats(target: &mut DiffStat, fixup: &DiffStat) {
+    target.files_changed += fixup.files_changed;
+    target.insertions += fixup.insertions;
+    target.deletions += fixup.deletions;
+    for stat in &fixup.paths {
+        match target.paths.iter_mut().find(|path| path.path == stat.path) {
+            Some(path) => {
+                path.insertions += stat.insertions;
+                path.deletions += stat.deletions;
+            },
+            None => target.paths.push(stat.clone())
+        }
+    }
+    target.paths.sort_by_key(|stat| std::cmp::Reverse(stat.insertions + stat.deletions));
+}
+
+// leaves out what would make the notes announce changes that aren't in the release: commits reverted within the range
+// along with their reverts, fixup! and squash! commits, which are collapsed into the commits they amend, and commits
+// whose patch-id is already reachable from prev_release_tag, such as cherry-picks backported to the previous release.
+// commits are expected newest first, as read_commits returns them
+pub fn clean_up_history(
+    repo: &Repository,
+    commits: Vec<CommitInfo>,
+    release_tag: &str,
+    prev_release_tag: &str,
+    credentials: &Credentials,
+    cancel: &CancelToken
+) -> Result<(Vec<CommitInfo>, Vec<RemovedCommit>)> {
+    let mut commits = commits.into_iter().map(Some).collect::<Vec<_>>();
+    let mut removed = vec![];
+    let remove = |commit: &CommitInfo, reason: RemovalReason, related: &str| RemovedCommit {
+        id: commit.id.clone(),
+        subject: subject(&commit.message).to_string(),
+        reason,
+        related: related.to_string()
+    };
+
+    // newest first, so a revert of a revert cancels out with the revert, and the originally reverted commit stays
+    for index in 0..commits.len() {
+        let Some(commit) = &commits[index] else {
+            continue;
+        };
+        let older = commits[index + 1..].iter().flatten();
+        let Some(reverted) = reverted(commit, older) else {
+            continue;
+        };
+
+        let reverted_index = commits.iter().position(|other| other.as_ref().is_some_and(|other| other.id == reverted.id));
+        removed.push(remove(reverted, RemovalReason::Reverted, &commit.id));
+        removed.push(remove(commit, RemovalReason::Revert, &reverted.id));
+        commits[index] = None;
+        if let Some(reverted_index) = reverted_index {
+            commits[reverted_index] = None;
+        }
+    }
+
+    // fixups of commits from earlier releases are changes of their own and stay
+    for index in 0..commits.len() {
+        let Some(commit) = &commits[index] else {
+            continue;
+        };
+        let older = commits[index + 1..].iter().flatten();
+        let Some((target, squash)) = fixed_up(commit, older) else {
+            continue;
+        };
 
-    let mut commit_messages: Vec<String> = vec![];
+        let target_id = target.id.clone();
+        let Some(commit) = commits[index].take() else {
+            continue;
+        };
+        removed.push(remove(&commit, RemovalReason::Fixup, &target_id));
+        if let Some(target) = commits.iter_mut().flatten().find(|other| other.id == target_id) {
+            merge_diffstats(&mut target.diffstat, &commit.diffstat);
+            // the message of a squash! commit is meant to be added to the one it amends
+            if squash {
+                let body = commit.message.split_once('\n').map(|(_, body)| body.trim()).unwrap_or_default();
+                if !body.is_empty() {
+                    target.message = format!("{}\n\n{body}", target.message.trim_end());
+                }
+            }
+        }
+    }
+
+    // commits the previous release has but this one doesn't, like `git log --cherry-pick --right-only prev...release`.
+    // there are none if the previous release is an ancestor of this one, so nothing has to be compared then
+    let (release_commit, prev_release_commit) = release_range(repo, release_tag, prev_release_tag)?;
+    let shipped = walk(repo, prev_release_commit.id(), release_commit.id())?
+        .into_iter()
+        .filter(|commit| commit.parent_count() <= 1)
+        .collect::<Vec<_>>();
+    if !shipped.is_empty() {
+        let remaining = commits
+            .iter()
+            .flatten()
+            .map(|commit| repo.find_commit(Oid::from_str(&commit.id)?))
+            .collect::<Result<Vec<_>, git2::Error>>()?
+            .into_iter()
+            .filter(|commit| commit.parent_count() <= 1)
+            .collect::<Vec<_>>();
+        fetch_trees(repo, shipped.iter().chain(&remaining), credentials, cancel)?;
+        let shipped_diffs = shipped
+            .iter()
+            .map(|commit| parent_diff(repo, commit))
+            .collect::<Result<Vec<_>>>()?;
+        let remaining_diffs = remaining
+            .iter()
+            .map(|commit| parent_diff(repo, commit))
+            .collect::<Result<Vec<_>>>()?;
+        fetch_blobs(repo, shipped_diffs.iter().chain(&remaining_diffs), credentials, cancel)?;
+
+        let mut shipped_patch_ids = vec![];
+        for (commit, diff) in shipped.iter().zip(&shipped_diffs) {
+            if cancel.is_cancelled() {
+                return Err(Cancelled.into());
+            }
+            shipped_patch_ids.push((diff.patchid(None)?, commit.id().to_string()));
+        }
+        for (commit, diff) in remaining.iter().zip(&remaining_diffs) {
+            if cancel.is_cancelled() {
+                return Err(Cancelled.into());
+            }
+            // an empty diff has the same patch-id as any other
+            if diff.deltas().len() == 0 {
+                continue;
+            }
+            let patch_id = diff.patchid(None)?;
+            let Some((_, shipped_id)) = shipped_patch_ids.iter().find(|(shipped, _)| *shipped == patch_id) else {
+                continue;
+            };
+
+            let id = commit.id().to_string();
+            if let Some(slot) = commits.iter_mut().find(|other| other.as_ref().is_some_and(|other| other.id == id)) {
+                if let Some(commit) = slot.take() {
+                    removed.push(remove(&commit, RemovalReason::AlreadyShipped, shipped_id));
+                }
+            }
+        }
+    }
+
+    return Ok((commits.into_iter().flatten().collect(), removed));
+}
+
+// the .mailmap of the release, which maps the names and emails people used over time to one of them
+fn read_mailmap(repo: &Repository, release_commit: &Commit, credentials: &Credentials, cancel: &CancelToken) -> Result<Mailmap> {
+    fetch_missing(repo, &[release_commit.tree_id()], credentials, cancel)?;
+    let Some(entry) = release_commit.tree()?.get_name(".mailmap").map(|entry| entry.id()) else {
+        return Ok(Mailmap::new()?);
+    };
+
+    fetch_missing(repo, &[entry], credentials, cancel)?;
+    let blob = repo.find_blob(entry)?;
+    return Ok(Mailmap::from_buffer(&String::from_utf8_lossy(blob.content()))?);
+}
+
+// the mailmapped author, committer and co-authors of a commit, bots left out
+fn identities(commit: &Commit, mailmap: &Mailmap, bots: &BotFilter) -> Result<Vec<(String, String)>> {
+    let mut signatures = vec![commit.author_with_mailmap(mailmap)?, commit.committer_with_mailmap(mailmap)?];
+    for (name, email) in co_authors(commit.message().unwrap_or_default()) {
+        // names git refuses, such as empty ones, can't be mapped
+        if let Ok(signature) = Signature::new(&name, &email, &commit.time()) {
+            signatures.push(mailmap.resolve_signature(&signature)?);
+        }
+    }
+
+    let mut identities: Vec<(String, String)> = vec![];
+    for signature in signatures {
+        let name = signature.name().unwrap_or_default().trim().to_string();
+        let email = signature.email().unwrap_or_default().trim().to_string();
+        if bots.is_bot(&name, &email) || identities.iter().any(|(other_name, other_email)| same_person(other_name, other_email, &name, &email)) {
+            continue;
+        }
+        identities.push((name, email));
+    }
+
+    return Ok(identities);
+}
+
+// by email, or by name for identities without one
+fn same_person(name: &str, email: &str, other_name: &str, other_email: &str) -> bool {
+    return match email.is_empty() || other_email.is_empty() {
+        true => name.eq_ignore_ascii_case(other_name),
+        false => email.eq_ignore_ascii_case(other_email)
+    };
+}
+
+// everyone who authored, co-authored or committed one of the commits, most commits first. first-time contributors
+// have no commit reachable from prev_release_tag, which may walk the whole history before it
+pub fn read_contributors(
+    repo: &Repository,
+    commits: &[CommitInfo],
+    release_tag: &str,
+    prev_release_tag: &str,
+    bots: &BotFilter,
+    credentials: &Credentials,
+    cancel: &CancelToken
+) -> Result<Vec<Contributor>> {
+    let (release_commit, prev_release_commit) = release_range(repo, release_tag, prev_release_tag)?;
+    let mailmap = read_mailmap(repo, &release_commit, credentials, cancel)?;
+
+    let mut contributors: Vec<Contributor> = vec![];
+    for commit in commits {
+        let commit = repo.find_commit(Oid::from_str(&commit.id)?)?;
+        for (name, email) in identities(&commit, &mailmap, bots)? {
+            match contributors.iter_mut().find(|contributor| same_person(&contributor.name, &contributor.email, &name, &email)) {
+                Some(contributor) => contributor.commits += 1,
+                None => contributors.push(Contributor { name, email, commits: 1, first_time: true })
+            }
+        }
+    }
+
+    // stops as soon as every contributor turned out to be a returning one
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(prev_release_commit.id())?;
     for commit_oid in revwalk {
+        if !contributors.iter().any(|contributor| contributor.first_time) {
+            break;
+        }
+        if cancel.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+
         let commit = repo.find_commit(commit_oid?)?;
+        for (name, email) in identities(&commit, &mailmap, bots)? {
+            for contributor in contributors.iter_mut().filter(|contributor| contributor.first_time) {
+                if same_person(&contributor.name, &contributor.email, &name, &email) {
+                    contributor.first_time = false;
+                }
+            }
+        }
+    }
+
+    contributors.sort_by(|a, b| b.commits.cmp(&a.commits).then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase())));
+
+    return Ok(contributors);
+}
+
+// a note `git notes` attached to one of the commits of the release
+#[derive(Clone, Debug)]
+pub struct CommitNote {
+    pub id: String,
+    pub subject: String,
+    pub note: String
+}
 
-        if commit.id() == prev_release_commit.id() {
-            return Ok(commit_messages);
+// what the maintainers wrote about the release themselves, which the notes should follow over the commits
+#[derive(Clone, Debug, Default)]
+pub struct Highlights {
+    pub tag_message: Option<String>,
+    pub notes: Vec<CommitNote>      // in the order of the commits, newest first
+}
+
+impl Highlights {
+    pub fn is_empty(&self) -> bool {
+        return self.tag_message.is_none() && self.notes.is_empty();
+    }
+
+    pub fn to_prompt(&self) -> String {
+        let mut prompt = "Highlights written by the maintainers:".to_string();
+        if let Some(tag_message) = &self.tag_message {
+            prompt += &format!("\nRelease tag message:\n{tag_message}");
+        }
+        if !self.notes.is_empty() {
+            prompt += "\nNotes on commits:";
+        }
+        for CommitNote { id, subject, note } in &self.notes {
+            prompt += &format!("\n- {} {subject}: {}", &id[..7.min(id.len())], note.replace('\n', "\n  "));
+        }
+
+        return prompt;
+    }
+}
+
+// the message of an annotated tag, without the signature of a signed one. lightweight tags and branches have none
+fn read_tag_message(repo: &Repository, release_tag: &str) -> Result<Option<String>> {
+    let reference = repo.resolve_reference_from_short_name(release_tag.trim())?;
+    let Some(tag) = reference.peel(ObjectType::Tag).ok().and_then(|tag| tag.into_tag().ok()) else {
+        return Ok(None);
+    };
+
+    let message = String::from_utf8_lossy(tag.message_bytes().unwrap_or_default()).to_string();
+    let end = ["-----BEGIN PGP SIGNATURE-----", "-----BEGIN SSH SIGNATURE-----", "-----BEGIN SIGNED MESSAGE-----"]
+        .iter()
+        .filter_map(|marker| message.find(marker))
+        .min()
+        .unwrap_or(message.len());
+    let message = message[..end].trim();
+
+    return Ok((!message.is_empty()).then(|| message.to_string()));
+}
+
+// the notes of every refs/notes/* ref attached to one of the commits. notes are stored as blobs in the trees of
+// the notes refs, which partial clones have to fetch first
+fn read_notes(repo: &Repository, commits: &[CommitInfo], credentials: &Credentials, cancel: &CancelToken) -> Result<Vec<CommitNote>> {
+    let mut notes_refs = vec![];
+    for reference in repo.references_glob("refs/notes/*")? {
+        let reference = reference?;
+        let (Some(name), Ok(commit)) = (reference.name(), reference.peel_to_commit()) else {
+            continue;
+        };
+        notes_refs.push((name.to_string(), commit.tree_id()));
+    }
+    fetch_missing(repo, &notes_refs.iter().map(|(_, tree)| *tree).collect::<Vec<_>>(), credentials, cancel)?;
+
+    // (annotated commit, note blob) of the commits of the release
+    let ids = commits.iter().map(|commit| commit.id.as_str()).collect::<HashSet<_>>();
+    let mut attached = vec![];
+    for (name, _) in &notes_refs {
+        for note in repo.notes(Some(name))? {
+            let (blob, annotated) = note?;
+            if ids.contains(annotated.to_string().as_str()) {
+                attached.push((annotated.to_string(), blob));
+            }
         }
+    }
+    fetch_missing(repo, &attached.iter().map(|(_, blob)| *blob).collect::<Vec<_>>(), credentials, cancel)?;
 
-        if let Some(message) = commit.message() {       // Commit::message will return None if the message is not valid utf-8
-            commit_messages.push(message.to_string());
+    let mut notes = vec![];
+    for commit in commits {
+        for (_, blob) in attached.iter().filter(|(annotated, _)| *annotated == commit.id) {
+            if cancel.is_cancelled() {
+                return Err(Cancelled.into());
+            }
+
+            let note = String::from_utf8_lossy(repo.find_blob(*blob)?.content()).trim().to_string();
+            if !note.is_empty() {
+                notes.push(CommitNote { id: commit.id.clone(), subject: subject(&commit.message).to_string(), note });
+            }
         }
     }
 
-    return Err(anyhow!("prev_release_tag doesn't precede release_tag."));
-}
\ No newline at end of file
+    return Ok(notes);
+}
+
+// the message of release_tag if it's an annotated tag, and with include_notes the notes of the commits, like
+// `git log --notes='*'`. partial clones may fetch the notes first, which fails with Cancelled once cancel is
+pub fn read_highlights(
+    repo: &Repository,
+    commits: &[CommitInfo],
+    release_tag: &str,
+    include_notes: bool,
+    credentials: &Credentials,
+    cancel: &CancelToken
+) -> Result<Highlights> {
+    let tag_message = read_tag_message(repo, release_tag)?;
+    let notes = match include_notes {
+        true => read_notes(repo, commits, credentials, cancel)?,
+        false => vec![]
+    };
+
+    return Ok(Highlights { tag_message, notes });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Time;
+
+    fn info(id: &str, message: &str) -> CommitInfo {
+        return CommitInfo {
+            id: id.to_string(),
+            author: "Jane Doe".to_string(),
+            author_email: "jane@example.com".to_string(),
+            date: chrono::DateTime::default(),
+            message: message.to_string(),
+            diffstat: DiffStat::default()
+        };
+    }
+
+    fn stat(path: &str, insertions: usize, deletions: usize) -> PathStat {
+        return PathStat { path: path.to_string(), insertions, deletions };
+    }
+
+    #[test]
+    fn finds_reverted_commits_by_id_or_subject() {
+        let older = [
+            info("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa", "feat: add export"),
+            info("bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb", "feat: add export\n\nAgain, after fixing it.")
+        ];
+
+        let message = "Revert \"feat: add export\"\n\nThis reverts commit bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb.";
+        let by_id = info("cccccccc", message);
+        assert_eq!(reverted(&by_id, older.iter()).map(|commit| &commit.id[..1]), Some("b"));
+        let by_subject = info("cccccccc", "Revert \"feat: add export\"");
+        assert_eq!(reverted(&by_subject, older.iter()).map(|commit| &commit.id[..1]), Some("a"));
+
+        assert!(reverted(&info("cccccccc", "Revert \"feat: add search\""), older.iter()).is_none());
+        assert!(reverted(&info("cccccccc", "feat: add export"), older.iter()).is_none());
+    }
+
+    #[test]
+    fn finds_fixed_up_commits() {
+        let older = [
+            info("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa", "feat: add search"),
+            info("bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb", "feat: add export")
+        ];
+
+        let (target, squash) = fixed_up(&info("c", "fixup! feat: add export"), older.iter()).unwrap();
+        assert_eq!((&target.id[..1], squash), ("b", false));
+        let squash_commit = info("c", "squash! fixup! feat: add search\n\nAnd faster.");
+        let (target, squash) = fixed_up(&squash_commit, older.iter()).unwrap();
+        assert_eq!((&target.id[..1], squash), ("a", true));
+        let (target, _) = fixed_up(&info("c", "fixup! bbbbbbb"), older.iter()).unwrap();
+        assert_eq!(&target.id[..1], "b");
+
+        // too short for an id, and not a subject either
+        assert!(fixed_up(&info("c", "fixup! bbb"), older.iter()).is_none());
+        assert!(fixed_up(&info("c", "fixup! feat: add import"), older.iter()).is_none());
+        assert!(fixed_up(&info("c", "feat: add export"), older.iter()).is_none());
+    }
+
+    #[test]
+    fn merges_diffstats_by_path() {
+        let mut target = DiffStat {
+            files_changed: 2,
+            insertions: 12,
+            deletions: 1,
+            paths: vec![stat("src/", 10, 1), stat("README.md", 2, 0)]
+        };
+        let fixup = DiffStat {
+            files_changed: 2,
+            insertions: 3,
+            deletions: 20,
+            paths: vec![stat("README.md", 3, 20), stat("docs/", 0, 0)]
+        };
+        merge_diffstats(&mut target, &fixup);
+
+        assert_eq!(target, DiffStat {
+            files_changed: 4,
+            insertions: 15,
+            deletions: 21,
+            paths: vec![stat("README.md", 5, 20), stat("src/", 10, 1), stat("docs/", 0, 0)]
+        });
+    }
+
+    // commits with the files of the first parent plus the given ones, a minute apart so the walk's order is known
+    // unless the clock is set back
+    struct TestRepo {
+        repo: Repository,
+        minutes: Cell<i64>
+    }
+
+    impl TestRepo {
+        fn commit_tree(&self, parents: &[Oid], tree: Oid, message: &str) -> Oid {
+            self.minutes.set(self.minutes.get() + 1);
+            let time = Time::new(1_700_000_000 + self.minutes.get() * 60, 0);
+            let signature = Signature::new("Jane Doe", "jane@example.com", &time).unwrap();
+            let tree = self.repo.find_tree(tree).unwrap();
+            let parents = parents.iter().map(|parent| self.repo.find_commit(*parent).unwrap()).collect::<Vec<_>>();
+            let parents = parents.iter().collect::<Vec<_>>();
+            return self.repo.commit(None, &signature, &signature, message, &tree, &parents).unwrap();
+        }
+
+        fn commit(&self, parents: &[Oid], files: &[(&str, &str)], message: &str) -> Oid {
+            let parent_tree = parents.first().map(|parent| self.repo.find_commit(*parent).unwrap().tree().unwrap());
+            let mut builder = self.repo.treebuilder(parent_tree.as_ref()).unwrap();
+            for (name, contents) in files {
+                let blob = self.repo.blob(contents.as_bytes()).unwrap();
+                builder.insert(name, blob, FileMode::Blob.into()).unwrap();
+            }
+            let tree = builder.write().unwrap();
+            return self.commit_tree(parents, tree, message);
+        }
+
+        fn tag(&self, name: &str, id: Oid) {
+            self.repo.reference(&format!("refs/tags/{name}"), id, false, "").unwrap();
+        }
+    }
+
+    #[test]
+    fn reads_merged_side_branches_regardless_of_dates() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = TestRepo { repo: Repository::init_bare(dir.path()).unwrap(), minutes: Cell::new(0) };
+
+        let base = repo.commit(&[], &[("README.md", "hello\n")], "Initial commit");
+        let parse = repo.commit(&[base], &[("parse.rs", "fn parse() {}\n")], "feat: add parser");
+        repo.tag("v1.0", parse);
+
+        // branched off before the previous release and committed on a machine whose clock was behind,
+        // so it looks older than everything in the previous release
+        repo.minutes.set(-1000);
+        let side = repo.commit(&[base], &[("export.rs", "fn export() {}\n")], "feat: add export");
+        repo.minutes.set(10);
+        let fix = repo.commit(&[parse], &[("parse.rs", "fn parse() -> bool {}\n")], "fix: handle empty input");
+        let merge = repo.commit(&[fix, side], &[("export.rs", "fn export() {}\n")], "Merge branch 'export'");
+        let search = repo.commit(&[merge], &[("search.rs", "fn search() {}\n")], "feat: add search");
+        repo.tag("v1.1", search);
+
+        let (credentials, cancel) = (Credentials::default(), CancelToken::default());
+        let filter = PathFilter::new(&[], &[]).unwrap();
+        let commits = read_commits(&repo.repo, "v1.1", "v1.0", &filter, &credentials, &cancel).unwrap();
+        let ids = commits.iter().map(|commit| commit.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids.len(), 4);
+        for id in [search, merge, fix, side] {
+            assert!(ids.contains(&id.to_string()), "{id}");
+        }
+        // newest first, and the merge itself has no diffstat
+        assert_eq!(ids[0], search.to_string());
+        let merge_info = commits.iter().find(|commit| commit.id == merge.to_string()).unwrap();
+        assert_eq!(merge_info.diffstat, DiffStat::default());
+        let side_info = commits.iter().find(|commit| commit.id == side.to_string()).unwrap();
+        assert_eq!((side_info.diffstat.files_changed, side_info.diffstat.insertions), (1, 1));
+
+        // a tag on a history of its own, such as an imported repo
+        let unrelated = repo.commit(&[], &[("README.md", "other\n")], "Initial commit");
+        repo.tag("other", unrelated);
+        let error = read_commits(&repo.repo, "v1.1", "other", &filter, &credentials, &cancel).unwrap_err();
+        assert_eq!(error.to_string(), "release_tag and prev_release_tag share no common history.");
+    }
+
+    #[test]
+    fn cleans_up_reverts_fixups_and_shipped_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = TestRepo { repo: Repository::init_bare(dir.path()).unwrap(), minutes: Cell::new(0) };
+
+        let base = repo.commit(&[], &[("README.md", "hello\n")], "Initial commit");
+        let fix = repo.commit(&[base], &[("parse.rs", "fn parse() {}\n")], "fix: handle empty input");
+        let export = repo.commit(&[fix], &[("export.rs", "fn export() {}\n")], "feat: add export");
+        let fix_tree = repo.repo.find_commit(fix).unwrap().tree_id();
+        let message = format!("Revert \"feat: add export\"\n\nThis reverts commit {export}.\n");
+        let revert = repo.commit_tree(&[export], fix_tree, &message);
+        let search = repo.commit(&[revert], &[("search.rs", "fn search() {}\n")], "feat: add search");
+        let files = [("search.rs", "fn search() {}\nfn index() {}\n")];
+        let fixup = repo.commit(&[search], &files, "fixup! feat: add search");
+        repo.tag("v1.1", fixup);
+
+        // the fix was cherry-picked onto the previous release, which branched off before it
+        let message = format!("fix: handle empty input\n\n(cherry picked from commit {fix})");
+        let backport = repo.commit(&[base], &[("parse.rs", "fn parse() {}\n")], &message);
+        repo.tag("v1.0", backport);
+
+        let (credentials, cancel) = (Credentials::default(), CancelToken::default());
+        let filter = PathFilter::new(&[], &[]).unwrap();
+        let commits = read_commits(&repo.repo, "v1.1", "v1.0", &filter, &credentials, &cancel).unwrap();
+        assert_eq!(commits.len(), 5);
+        let (commits, removed) = clean_up_history(&repo.repo, commits, "v1.1", "v1.0", &credentials, &cancel).unwrap();
+
+        let removed = removed
+            .iter()
+            .map(|commit| (commit.id.clone(), commit.reason.clone(), commit.related.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(removed, [
+            (export.to_string(), RemovalReason::Reverted, revert.to_string()),
+            (revert.to_string(), RemovalReason::Revert, export.to_string()),
+            (fixup.to_string(), RemovalReason::Fixup, search.to_string()),
+            (fix.to_string(), RemovalReason::AlreadyShipped, backport.to_string())
+        ]);
+
+        // the fixup is collapsed into the commit it amends
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0].id, search.to_string());
+        assert_eq!((commits[0].diffstat.files_changed, commits[0].diffstat.insertions), (2, 2));
+    }
+}
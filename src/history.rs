@@ -0,0 +1,144 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::path::Path;
+
+use crate::config;
+use crate::util::{Arguments, Run, RunSummary};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        created_at TEXT NOT NULL,
+        repo_link TEXT NOT NULL,
+        product_name TEXT NOT NULL,
+        release_tag TEXT NOT NULL,
+        prev_release_tag TEXT NOT NULL,
+        release_commit TEXT NOT NULL,
+        prev_release_commit TEXT NOT NULL,
+        arguments TEXT NOT NULL,
+        prompt_version TEXT NOT NULL,
+        model TEXT NOT NULL,
+        release_notes TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS runs_created_at ON runs (created_at);
+";
+
+// a single SQLite file, opened per request since connections are cheap and requests are few
+pub struct History {
+    connection: Connection
+}
+
+impl History {
+    pub fn open(path: &Path) -> Result<Self> {
+        let connection = Connection::open(path)
+            .map_err(|error| anyhow!("Unable to open the history database {}: {error}", path.display()))?;
+        connection.execute_batch(SCHEMA)?;
+
+        return Ok(History { connection });
+    }
+
+    pub fn from_config() -> Result<Self> {
+        return History::open(&config::get().history.database);
+    }
+
+    // rusqlite blocks, so async code runs its queries on a blocking thread with the database from the config
+    pub async fn blocking<T, F>(query: F) -> Result<T>
+    where T: Send + 'static, F: FnOnce(&History) -> Result<T> + Send + 'static {
+        return tokio::task::spawn_blocking(move || query(&History::from_config()?)).await?;
+    }
+
+    pub fn save(&self, run: &Run) -> Result<i64> {
+        let Arguments { repo_link, product_name, release_tag, prev_release_tag, .. } = &run.arguments;
+
+        self.connection.execute(
+            "INSERT INTO runs (
+                created_at, repo_link, product_name, release_tag, prev_release_tag, release_commit,
+                prev_release_commit, arguments, prompt_version, model, release_notes
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                run.created_at,
+                repo_link,
+                product_name,
+                release_tag,
+                prev_release_tag,
+                run.release_commit,
+                run.prev_release_commit,
+                serde_json::to_string(&run.arguments)?,
+                run.prompt_version,
+                run.model,
+                serde_json::to_string(&run.release_notes)?
+            ]
+        )?;
+
+        return Ok(self.connection.last_insert_rowid());
+    }
+
+    // newest first
+    pub fn list(&self) -> Result<Vec<RunSummary>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, created_at, repo_link, product_name, release_tag, prev_release_tag, model
+            FROM runs ORDER BY created_at DESC, id DESC")?;
+
+        let runs = statement
+            .query_map([], |row| {
+                return Ok(RunSummary {
+                    id: row.get(0)?,
+                    created_at: row.get(1)?,
+                    repo_link: row.get(2)?,
+                    product_name: row.get(3)?,
+                    release_tag: row.get(4)?,
+                    prev_release_tag: row.get(5)?,
+                    model: row.get(6)?
+                });
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        return Ok(runs);
+    }
+
+    pub fn get(&self, id: i64) -> Result<Option<Run>> {
+        let row = self.connection
+            .query_row(
+                "SELECT id, created_at, release_commit, prev_release_commit, arguments, prompt_version, model, release_notes
+                FROM runs WHERE id = ?1",
+                [id],
+                read_run)
+            .optional()?;
+
+        return row.transpose();
+    }
+
+    // returns whether there was a run to delete
+    pub fn delete(&self, id: i64) -> Result<bool> {
+        let deleted = self.connection.execute("DELETE FROM runs WHERE id = ?1", [id])?;
+        return Ok(deleted > 0);
+    }
+}
+
+// the JSON columns are parsed outside of rusqlite so a corrupt row surfaces as a readable error
+fn read_run(row: &Row) -> rusqlite::Result<Result<Run>> {
+    let id: i64 = row.get(0)?;
+    let created_at: DateTime<Utc> = row.get(1)?;
+    let release_commit: String = row.get(2)?;
+    let prev_release_commit: String = row.get(3)?;
+    let arguments: String = row.get(4)?;
+    let prompt_version: String = row.get(5)?;
+    let model: String = row.get(6)?;
+    let release_notes: String = row.get(7)?;
+
+    let parse = || -> Result<Run> {
+        return Ok(Run {
+            id,
+            created_at,
+            release_commit,
+            prev_release_commit,
+            arguments: serde_json::from_str(&arguments)?,
+            prompt_version,
+            model,
+            release_notes: serde_json::from_str(&release_notes)?
+        });
+    };
+
+    return Ok(parse().map_err(|error| anyhow!("Run {id} is corrupt: {error}")));
+}
@@ -0,0 +1,165 @@
+use leptos::*;
+
+use crate::app::ReleaseNotesView;
+use crate::util::{Run, RunSummary};
+
+#[server(ListRuns, "/api")]
+pub async fn list_runs() -> Result<Vec<RunSummary>, ServerFnError> {
+    use crate::history::History;
+
+    return History::blocking(|history| history.list()).await.map_err(ServerFnError::new);
+}
+
+#[server(GetRun, "/api")]
+pub async fn get_run(id: i64) -> Result<Option<Run>, ServerFnError> {
+    use crate::history::History;
+
+    return History::blocking(move |history| history.get(id)).await.map_err(ServerFnError::new);
+}
+
+// takes the admin token like DELETE /api/v1/admin/runs/:id, since anyone who can open the page could delete otherwise
+#[server(DeleteRun, "/api")]
+pub async fn delete_run(id: i64, admin_token: String) -> Result<(), ServerFnError> {
+    use crate::api::is_admin_token;
+    use crate::history::History;
+
+    if !is_admin_token(&admin_token) {
+        return Err(ServerFnError::new("A valid admin token is required."));
+    }
+    if !History::blocking(move |history| history.delete(id)).await.map_err(ServerFnError::new)? {
+        return Err(ServerFnError::new(format!("Run {id} doesn't exist.")));
+    }
+
+    return Ok(());
+}
+
+// matches the product, either tag or the repository, so "v2.3" finds the notes for that release
+fn matches(run: &RunSummary, search: &str) -> bool {
+    let search = search.trim().to_lowercase();
+    return [&run.product_name, &run.release_tag, &run.prev_release_tag, &run.repo_link]
+        .iter()
+        .any(|field| field.to_lowercase().contains(&search));
+}
+
+#[component]
+pub fn HistoryPage() -> impl IntoView {
+    let delete = create_action(|(id, admin_token): &(i64, String)| delete_run(*id, admin_token.clone()));
+    // listed again after every deletion
+    let runs = create_resource(move || delete.version().get(), |_| list_runs());
+    let (search, set_search) = create_signal("".to_string());
+    let (admin_token, set_admin_token) = create_signal("".to_string());
+    let (selected, set_selected) = create_signal(None::<i64>);
+    let run = create_local_resource(selected, |id| async move {
+        match id {
+            Some(id) => get_run(id).await,
+            None => Ok(None)
+        }
+    });
+    let release_notes = Signal::derive(move || run
+        .get()
+        .and_then(|run| run.ok())
+        .flatten()
+        .map(|run| run.release_notes));
+
+    let on_delete = move |id: i64| {
+        if window().confirm_with_message("Delete these release notes?").unwrap_or(false) {
+            if selected.get_untracked() == Some(id) {
+                set_selected(None);
+            }
+            delete.dispatch((id, admin_token.get_untracked()));
+        }
+    };
+
+    view! {
+        <h1 class="text-[1.5em]">"History"</h1>
+        <div class="grid grid-cols-[50vw_40vw]">
+            <div class="px-[2vw] py-[6vh]">
+                <input
+                    class="w-[25em] mb-[3vh] px-[3px] text-[1rem] placeholder-gray-500 bg-gray-200 border-2 border-black"
+                    type="text"
+                    value=search
+                    on:input = move |event| set_search(event_target_value(&event))
+                    placeholder = "Search by product, tag or repository" />
+                <input
+                    class="w-[15em] mb-[3vh] ml-[1em] px-[3px] text-[1rem] placeholder-gray-500 bg-gray-200 border-2 border-black"
+                    type="password"
+                    value=admin_token
+                    on:input = move |event| set_admin_token(event_target_value(&event))
+                    placeholder = "Admin token, to delete" />
+                <Transition fallback=move || view! { <p>"Loading..."</p> }>
+                    {move || runs.get().map(|runs| match runs {
+                        Err(error) => view! {
+                            <p class="text-red-600">{format!("Unable to load the history: {error}")}</p>
+                        }.into_view(),
+                        Ok(runs) if runs.is_empty() => view! {
+                            <p>"No release notes have been generated yet."</p>
+                        }.into_view(),
+                        Ok(runs) => view! {
+                            <table class="text-[0.9rem]">
+                                <tr class="text-left">
+                                    <th class="pr-[1em]">"Generated"</th>
+                                    <th class="pr-[1em]">"Product"</th>
+                                    <th class="pr-[1em]">"Range"</th>
+                                    <th class="pr-[1em]">"Model"</th>
+                                </tr>
+                                {runs
+                                    .into_iter()
+                                    .filter(|run| matches(run, &search()))
+                                    .map(|run| {
+                                        let id = run.id;
+                                        view! {
+                                            <tr class:font-semibold=move || selected() == Some(id)>
+                                                <td class="pr-[1em]">{run.created_at.format("%Y-%m-%d %H:%M").to_string()}</td>
+                                                <td class="pr-[1em]" title=run.repo_link>{run.product_name}</td>
+                                                <td class="pr-[1em]">{format!("{}..{}", run.prev_release_tag, run.release_tag)}</td>
+                                                <td class="pr-[1em]">{run.model}</td>
+                                                <td class="pr-[0.5em]">
+                                                    <button
+                                                        class="px-[0.5em] border-2 border-black hover:bg-gray-200"
+                                                        on:click=move |_| set_selected(Some(id))
+                                                    >"View"</button>
+                                                </td>
+                                                <td>
+                                                    <button
+                                                        class="px-[0.5em] border-2 border-black hover:bg-gray-200"
+                                                        on:click=move |_| on_delete(id)
+                                                    >"Delete"</button>
+                                                </td>
+                                            </tr>
+                                        }
+                                    })
+                                    .collect_view()}
+                            </table>
+                        }.into_view()
+                    })}
+                </Transition>
+                <p
+                    class="text-red-600"
+                    style:display=move || delete.value().get().is_none_or(|result| result.is_ok()).then_some("None")
+                >{move || delete.value().get().and_then(|result| result.err()).map(|error| error.to_string())}</p>
+            </div>
+            <div>
+                <ReleaseNotesView release_notes />
+                {move || run.get().and_then(|run| run.ok()).flatten().map(|run| view! {
+                    <p class="text-[0.8rem] text-gray-600">{format!(
+                        "{}..{} ({}..{}), {:?} audience, prompt version {}",
+                        run.arguments.prev_release_tag,
+                        run.arguments.release_tag,
+                        run.prev_release_commit.get(..7).unwrap_or(&run.prev_release_commit),
+                        run.release_commit.get(..7).unwrap_or(&run.release_commit),
+                        run.arguments.target_audience,
+                        run.prompt_version
+                    )}</p>
+                    <p
+                        class="text-[0.8rem] text-gray-600"
+                        style:display=(run.arguments.include_paths.is_empty() && run.arguments.exclude_paths.is_empty()).then_some("None")
+                    >{format!(
+                        "Paths: {}, excluding: {}",
+                        if run.arguments.include_paths.is_empty() { "all".to_string() } else { run.arguments.include_paths.join(", ") },
+                        if run.arguments.exclude_paths.is_empty() { "none".to_string() } else { run.arguments.exclude_paths.join(", ") }
+                    )}</p>
+                })}
+            </div>
+        </div>
+    }
+}
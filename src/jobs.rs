@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::cancel::CancelToken;
+use crate::submit::spawn_request;
+use crate::util::{Arguments, ServerMessage};
+
+// finished jobs can be reattached to for this long
+const FINISHED_JOB_TTL: Duration = Duration::from_secs(60 * 60);
+// running jobs without a client are cancelled after this long, which leaves the form time to reconnect
+const DETACHED_JOB_GRACE: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct JobState {
+    messages: Vec<ServerMessage>,
+    finished_at: Option<Instant>
+}
+
+// where a client is in the messages of a job. consecutive tokens are kept as one message, which may still grow
+// after a client has read part of it
+#[derive(Default)]
+pub struct Cursor {
+    index: usize,
+    offset: usize       // bytes of the token message at index already read
+}
+
+// every message of a generation is kept, so clients that attach later get the whole run replayed. the tokens of
+// the notes are merged, since there are thousands of them
+pub struct Job {
+    pub id: String,
+    state: Mutex<JobState>,
+    notify: Notify,
+    cancel: CancelToken,
+    attached: AtomicUsize,
+    attachments: AtomicUsize    // every attach so far, tells whether a client came and went during the grace period
+}
+
+// held by every client following a job, see Job::attach
+pub struct Attachment(Arc<Job>);
+
+impl Drop for Attachment {
+    fn drop(&mut self) {
+        if self.0.attached.fetch_sub(1, Ordering::SeqCst) != 1 {
+            return;
+        }
+
+        let job = self.0.clone();
+        let attachments = job.attachments.load(Ordering::SeqCst);
+        tokio::spawn(async move {
+            tokio::time::sleep(DETACHED_JOB_GRACE).await;
+            if job.attachments.load(Ordering::SeqCst) == attachments {
+                job.cancel("no client was attached");
+            }
+        });
+    }
+}
+
+impl Job {
+    fn push(&self, message: ServerMessage) {
+        {
+            let mut state = self.state.lock().unwrap();
+            match (state.messages.last_mut(), message) {
+                (Some(ServerMessage::Token { token }), ServerMessage::Token { token: next }) => token.push_str(&next),
+                (_, message) => state.messages.push(message)
+            }
+        }
+        self.notify.notify_waiters();
+    }
+
+    fn finish(&self) {
+        self.state.lock().unwrap().finished_at = Some(Instant::now());
+        self.notify.notify_waiters();
+    }
+
+    fn finished(&self) -> bool {
+        return self.state.lock().unwrap().finished_at.is_some();
+    }
+
+    // stops the generation unless it has already finished
+    pub fn cancel(&self, reason: &str) {
+        if !self.finished() {
+            self.cancel.cancel(reason);
+        }
+    }
+
+    // the job is cancelled once the last attachment has been dropped for DETACHED_JOB_GRACE
+    pub fn attach(self: &Arc<Self>) -> Attachment {
+        self.attached.fetch_add(1, Ordering::SeqCst);
+        self.attachments.fetch_add(1, Ordering::SeqCst);
+        return Attachment(self.clone());
+    }
+
+    fn expired(&self) -> bool {
+        return self.state
+            .lock()
+            .unwrap()
+            .finished_at
+            .is_some_and(|finished_at| finished_at.elapsed() > FINISHED_JOB_TTL);
+    }
+
+    // waits for the message after cursor and moves it on, None once the job has finished without one.
+    // tokens come as whatever was appended since the client last read them
+    pub async fn message(&self, cursor: &mut Cursor) -> Option<ServerMessage> {
+        loop {
+            // registered before looking at the state so a message pushed in between isn't missed
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            {
+                let state = self.state.lock().unwrap();
+                while let Some(message) = state.messages.get(cursor.index) {
+                    let ServerMessage::Token { token } = message else {
+                        cursor.index += 1;
+                        return Some(message.clone());
+                    };
+
+                    if cursor.offset < token.len() {
+                        let unread = token[cursor.offset..].to_string();
+                        cursor.offset = token.len();
+                        return Some(ServerMessage::Token { token: unread });
+                    }
+                    // the last message may still grow
+                    if cursor.index + 1 == state.messages.len() {
+                        break;
+                    }
+                    cursor.index += 1;
+                    cursor.offset = 0;
+                }
+                if state.finished_at.is_some() {
+                    return None;
+                }
+            }
+
+            notified.await;
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Mutex<HashMap<String, Arc<Job>>>
+}
+
+impl Jobs {
+    pub fn global() -> &'static Jobs {
+        static JOBS: OnceLock<Jobs> = OnceLock::new();
+        return JOBS.get_or_init(Jobs::default);
+    }
+
+    // job ids are the only thing standing between a client and someone else's notes, so they come from the
+    // operating system's random number generator
+    fn new_id() -> String {
+        return Uuid::new_v4().simple().to_string();
+    }
+
+    // the job is removed FINISHED_JOB_TTL after it has finished
+    pub fn start(&'static self, arguments: Arguments) -> Arc<Job> {
+        let job = Arc::new(Job {
+            id: Jobs::new_id(),
+            state: Mutex::new(JobState::default()),
+            notify: Notify::new(),
+            cancel: CancelToken::default(),
+            attached: AtomicUsize::new(0),
+            attachments: AtomicUsize::new(0)
+        });
+
+        self.jobs.lock().unwrap().insert(job.id.clone(), job.clone());
+
+        let running_job = job.clone();
+        tokio::spawn(async move {
+            let mut recv = spawn_request(arguments, running_job.cancel.clone());
+            while let Some(message) = recv.recv().await {
+                running_job.push(message);
+            }
+            running_job.finish();
+
+            tokio::time::sleep(FINISHED_JOB_TTL).await;
+            self.jobs.lock().unwrap().remove(&running_job.id);
+        });
+
+        return job;
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<Job>> {
+        return self.jobs
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|job| !job.expired())
+            .cloned();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn job() -> Job {
+        return Job {
+            id: Jobs::new_id(),
+            state: Mutex::new(JobState::default()),
+            notify: Notify::new(),
+            cancel: CancelToken::default(),
+            attached: AtomicUsize::new(0),
+            attachments: AtomicUsize::new(0)
+        };
+    }
+
+    fn token(token: &str) -> ServerMessage {
+        return ServerMessage::Token { token: token.to_string() };
+    }
+
+    fn warning(message: &str) -> ServerMessage {
+        return ServerMessage::Warning { message: message.to_string() };
+    }
+
+    #[test]
+    fn ids_are_random() {
+        let id = Jobs::new_id();
+        assert_eq!(id.len(), 32);
+        assert_ne!(id, Jobs::new_id());
+    }
+
+    #[test]
+    fn tokens_are_merged() {
+        let job = job();
+        for message in [warning("a"), token("{"), token("\"overview\""), token(": "), warning("b")] {
+            job.push(message);
+        }
+        job.finish();
+
+        let mut cursor = Cursor::default();
+        assert_eq!(block_on(job.message(&mut cursor)), Some(warning("a")));
+        assert_eq!(block_on(job.message(&mut cursor)), Some(token("{\"overview\": ")));
+        assert_eq!(block_on(job.message(&mut cursor)), Some(warning("b")));
+        assert_eq!(block_on(job.message(&mut cursor)), None);
+    }
+
+    #[test]
+    fn clients_following_get_the_appended_tokens() {
+        let job = job();
+        job.push(token("{"));
+
+        let mut cursor = Cursor::default();
+        assert_eq!(block_on(job.message(&mut cursor)), Some(token("{")));
+        job.push(token("\"overview\""));
+        assert_eq!(block_on(job.message(&mut cursor)), Some(token("\"overview\"")));
+        job.push(warning("a"));
+        assert_eq!(block_on(job.message(&mut cursor)), Some(warning("a")));
+        job.finish();
+        assert_eq!(block_on(job.message(&mut cursor)), None);
+    }
+}
@@ -1,16 +1,57 @@
 pub mod util;
 
+#[cfg(feature = "ssr")]
+pub mod config;
+
 #[cfg(feature = "ssr")]
 pub mod git;
 
+#[cfg(feature = "ssr")]
+pub mod sandbox;
+
+#[cfg(feature = "ssr")]
+pub mod cache;
+
+#[cfg(feature = "ssr")]
+pub mod commits;
+
+#[cfg(feature = "ssr")]
+pub mod tickets;
+
+#[cfg(feature = "ssr")]
+pub mod contributors;
+
+#[cfg(feature = "ssr")]
+pub mod credentials;
+
 #[cfg(feature = "ssr")]
 pub mod fetch_tokens;
 
+#[cfg(feature = "ssr")]
+pub mod summarize;
+
 #[cfg(feature = "ssr")]
 pub mod submit;
 
+#[cfg(feature = "ssr")]
+pub mod jobs;
+
+#[cfg(feature = "ssr")]
+pub mod cancel;
+
+#[cfg(feature = "ssr")]
+pub mod metrics;
+
+#[cfg(feature = "ssr")]
+pub mod history;
+
+#[cfg(feature = "ssr")]
+pub mod api;
+
+pub mod tag_picker;
 pub mod ticket_form;
 pub mod form;
+pub mod history_page;
 pub mod app;
 #[cfg(feature = "ssr")]
 pub mod fileserv;
@@ -5,6 +5,7 @@ async fn main() {
     use leptos::*;
     use leptos_axum::{generate_route_list, LeptosRoutes};
     use releasenotes::app::*;
+    use releasenotes::api;
     use releasenotes::submit::submit;
     use releasenotes::fileserv::file_and_error_handler;
 
@@ -13,6 +14,19 @@ async fn main() {
     // <https://github.com/leptos-rs/start-axum#executing-a-server-on-a-remote-machine-without-the-toolchain>
     // Alternately a file can be specified such as Some("Cargo.toml")
     // The file would need to be included with the executable when moved to deployment
+    // the config is validated before anything else, so a broken deployment fails right away
+    match releasenotes::config::init() {
+        Ok(config) => {
+            for warning in config.warnings() {
+                eprintln!("Warning: {warning}");
+            }
+        },
+        Err(error) => {
+            eprintln!("Invalid configuration: {error}");
+            std::process::exit(1);
+        }
+    }
+
     let conf = get_configuration(None).await.unwrap();
     let leptos_options = conf.leptos_options;
     let addr = leptos_options.site_addr;
@@ -21,6 +35,14 @@ async fn main() {
     // build our application with a route
     let app = Router::new()
         .route("/submit", routing::get(submit))
+        .route("/api/v1/release-notes", routing::post(api::generate))
+        .route("/api/v1/release-notes/stream", routing::post(api::generate_stream))
+        .route("/api/v1/jobs/:id", routing::delete(api::cancel_job))
+        .route("/api/v1/jobs/:id/stream", routing::get(api::attach_stream))
+        .route("/api/v1/admin/metrics", routing::get(api::metrics))
+        .route("/api/v1/admin/repos", routing::get(api::list_repos).delete(api::purge_repos))
+        .route("/api/v1/admin/repos/:digest", routing::delete(api::purge_repo))
+        .route("/api/v1/admin/runs/:id", routing::delete(api::delete_run))
         .leptos_routes(&leptos_options, routes, RootApp)
         .fallback(file_and_error_handler)
         .with_state(leptos_options);
@@ -0,0 +1,35 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+use crate::util::ErrorKind;
+
+// counters since the server started, served on /api/v1/admin/metrics
+#[derive(Default, Serialize)]
+pub struct Metrics {
+    pub generations_started: AtomicU64,
+    pub generations_succeeded: AtomicU64,
+    pub generations_failed: AtomicU64,
+    pub generations_cancelled: AtomicU64
+}
+
+impl Metrics {
+    pub fn global() -> &'static Metrics {
+        static METRICS: OnceLock<Metrics> = OnceLock::new();
+        return METRICS.get_or_init(Metrics::default);
+    }
+
+    pub fn started(&self) {
+        self.generations_started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // with the kind of error the generation ended with, if any
+    pub fn finished(&self, error: Option<ErrorKind>) {
+        let counter = match error {
+            None => &self.generations_succeeded,
+            Some(ErrorKind::Cancelled) => &self.generations_cancelled,
+            Some(_) => &self.generations_failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -1,66 +1,323 @@
 use anyhow::{anyhow, Result};
 use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
 use futures::StreamExt;
-use tokio::sync::mpsc;
-
-use crate::fetch_tokens::fetch_tokens;
-use crate::git::{read_commit_messages, fetch_or_clone};
-use crate::util::{Arguments, TargetAudience, Ticket};
-
-fn generate_prompt(
-    product_name: &str,
-    release_version: &str,
-    release_date: chrono::NaiveDate,
-    target_audience: TargetAudience,
-    tickets: Vec<Ticket>,
-    commit_messages: Vec<String>
-) -> String {
-    let directive = format!("IMPORTANT: Your target audience is: {target_audience:?}. You must take this into account.");
-
-    let prompt = format!("Template:\n{product_name} Release Notes - {release_version} - {release_date}\n\n")
-        + include_str!("./templates/template.md");
-
-    return format!("Tickets:\n{}\n\nCommit messages:\n{}\n\n{directive}\n\n{prompt}",
+use std::sync::OnceLock;
+use std::time::Instant;
+use tokio::sync::{mpsc, Semaphore};
+
+use crate::cancel::{CancelToken, Cancelled};
+use crate::commits::{CategorizedCommits, Classifier};
+use crate::fetch_tokens::{LlmConfig, Metered, Provider, ResponseFormat};
+use crate::config;
+use crate::tickets::{LinkedTicket, TicketLinker, TicketLinks};
+use crate::summarize::{collect_tokens, condense, estimate_tokens, Changes};
+use crate::contributors::BotFilter;
+use crate::git::{
+    clean_up_history, read_commits, read_contributors, read_highlights, fetch_or_clone, resolve_commit, CommitInfo, Highlights,
+    PathFilter
+};
+use crate::sandbox::Refusal;
+use crate::history::History;
+use crate::jobs::{Cursor, Jobs};
+use crate::metrics::Metrics;
+use crate::util::{Arguments, ClientMessage, Contributor, Envelope, ErrorKind, ReleaseNotes, Run, ServerMessage};
+
+pub fn generate_prompt(arguments: &Arguments, highlights: &Highlights, tickets: &[LinkedTicket], changes: &Changes) -> String {
+    let Arguments { product_name, release_tag, release_date, target_audience, include_paths, exclude_paths, .. } = arguments;
+    let mut directive = format!("IMPORTANT: Your target audience is: {target_audience:?}. You must take this into account.");
+    if changes.has_breaking_changes() {
+        directive += " This release contains breaking changes, each of which must be called out explicitly.";
+    }
+
+    let mut prompt = format!("Product: {product_name}\nVersion: {release_tag}\nRelease date: {release_date}\n");
+    // the product is one component of a larger repo, and the commits only those changing it
+    if !include_paths.is_empty() {
+        prompt += &format!("Paths: {}\n", include_paths.join(", "));
+    }
+    if !exclude_paths.is_empty() {
+        prompt += &format!("Excluded paths: {}\n", exclude_paths.join(", "));
+    }
+    prompt += "\nRespond with a JSON object in exactly this format:\n";
+    prompt += &config::get().templates.release_notes_format;
+
+    // written by hand, so they come first
+    let highlights = match highlights.is_empty() {
+        true => String::new(),
+        false => format!("{}\n\n", highlights.to_prompt())
+    };
+
+    return format!("{highlights}Tickets:\n{}\n\n{}\n\n{directive}\n\n{prompt}",
         tickets
             .iter()
-            .map(|ticket|
-                format!("Summary:{}\nDescription:{}", ticket.summary, ticket.description))
+            .map(LinkedTicket::to_prompt)
             .collect::<Vec<_>>()
             .join("\n--------------------\n"),
-        commit_messages.join("\n"));
+        changes.to_prompt());
 }
 
-async fn handle_request(arguments: Arguments, sender: mpsc::UnboundedSender<String>) -> Result<()> {
-    if arguments.any_field_empty() {
-        return Err(anyhow!("A field has been left empty."));
+// commits that don't fit into the context window are summarized in batches first, reporting each batch through on_event
+async fn prepare_prompt<F>(
+    config: &LlmConfig,
+    provider: &dyn Provider,
+    arguments: &Arguments,
+    highlights: &Highlights,
+    tickets: &[LinkedTicket],
+    commits: CategorizedCommits,
+    on_event: F
+) -> Result<String> where F: Fn(ServerMessage) {
+    let templates = &config::get().templates;
+
+    let empty_changes = Changes::Commits(CategorizedCommits::default());
+    let fixed_tokens = estimate_tokens(&templates.system)
+        + estimate_tokens(&generate_prompt(arguments, highlights, tickets, &empty_changes));
+    let final_budget = config.prompt_budget()
+        .checked_sub(fixed_tokens)
+        .ok_or(anyhow!("The tickets and highlights are too large to fit into the context window."))?;
+    let batch_budget = config.prompt_budget().saturating_sub(estimate_tokens(&templates.summarize) + 16);
+
+    let changes = condense(provider, &templates.summarize, commits, batch_budget, final_budget, on_event).await?;
+    return Ok(generate_prompt(arguments, highlights, tickets, &changes));
+}
+
+// models sometimes wrap the JSON in a markdown code block regardless of the instructions
+fn parse_release_notes(output: &str) -> Result<ReleaseNotes> {
+    let json = match (output.find('{'), output.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &output[start..=end],
+        _ => output
+    };
+
+    return serde_json::from_str(json)
+        .map_err(|error| anyhow!("The model didn't respond with valid release notes: {error}"));
+}
+
+// shared by every entry point: condenses the commits if needed, then generates the notes and validates them
+pub async fn generate_release_notes<F>(
+    config: &LlmConfig,
+    provider: &dyn Provider,
+    arguments: &Arguments,
+    highlights: &Highlights,
+    commits: CategorizedCommits,
+    on_event: F
+) -> Result<ReleaseNotes> where F: Fn(ServerMessage) {
+    if commits.is_empty() {
+        let message = match arguments.include_paths.is_empty() && arguments.exclude_paths.is_empty() {
+            true => "There are no commits between the tags, the release notes are based on the tickets alone.",
+            false => "No commit between the tags changes the paths, the release notes are based on the tickets alone."
+        };
+        on_event(ServerMessage::Warning { message: message.to_string() });
     }
 
-    let Arguments { repo_link, product_name, release_tag, prev_release_tag, release_date, target_audience, tickets } = arguments;
+    // linked before the commits may be summarized, so every ticket still lists its commits
+    let linker = TicketLinker::new(&config::get().tickets.reference_patterns)?;
+    let TicketLinks { tickets, report } = linker.link(&arguments.tickets, &commits);
+    on_event(ServerMessage::TicketLinks { report });
+
+    let prompt = prepare_prompt(config, provider, arguments, highlights, &tickets, commits, &on_event).await?;
+
+    on_event(ServerMessage::Progress { stage: "Generating release notes".to_string(), percent: None });
+    let output = collect_tokens(
+        provider.fetch_tokens(&prompt, &config::get().templates.system, ResponseFormat::Json),
+        |token| on_event(ServerMessage::Token { token: token.to_string() })
+    ).await?;
+    let mut release_notes = parse_release_notes(&output)?;
+    release_notes.product_name = arguments.product_name.clone();
+    release_notes.version = arguments.release_tag.clone();
+    release_notes.release_date = arguments.release_date;
+
+    return Ok(release_notes);
+}
+
+// the kind tells clients whether to fix their input, the repository or the LLM configuration
+struct RequestError(ErrorKind, anyhow::Error);
+
+// what the release is made of according to git
+struct Release {
+    // the commits both tags point to
+    release_commit: String,
+    prev_release_commit: String,
+    commits: Vec<CommitInfo>,
+    contributors: Vec<Contributor>,     // empty unless the arguments credit them
+    highlights: Highlights
+}
+
+// libgit2 blocks until a clone or fetch has finished, so this runs on a blocking thread and sends its progress from there
+fn read_release<F>(arguments: &Arguments, on_event: F, cancel: &CancelToken) -> Result<Release, RequestError>
+where F: Fn(ServerMessage) {
+    // partial clones fetch what they're missing while the commits are read, which may be refused or cancelled as well
+    let git_error = |error: anyhow::Error| {
+        let kind = match error.downcast_ref::<Refusal>() {
+            Some(refusal) => refusal.kind(),
+            None if error.is::<Cancelled>() => ErrorKind::Cancelled,
+            None => ErrorKind::Git
+        };
+        return RequestError(kind, error);
+    };
 
     // fetch from origin if repo is already present, otherwise clone from link
     // tags don't need to be merged into local, so fetching is enough
-    let mut repo = fetch_or_clone(repo_link)?;
-    let commit_messages = read_commit_messages(&mut repo, &release_tag, &prev_release_tag)?;
-
-    let api_key = std::env::var("OPENAI_API_KEY").map_err(|_| anyhow!("No OpenAI API Key"))?;
-    let prompt = generate_prompt(&product_name, &release_tag, release_date, target_audience, tickets, commit_messages);
-    let mut token_stream = fetch_tokens(&api_key, &prompt, include_str!("./templates/prompt.txt"));
-    sender.send("Streaming".to_string())?;
-    while let Some(token) = token_stream.next().await {
-        match token {
-            Ok(Some(token)) => {
-                sender.send(token)?;
-            },
-            Ok(None) => {
-                break;
+    on_event(ServerMessage::Progress { stage: "Cloning".to_string(), percent: None });
+    let on_progress = |progress| on_event(ServerMessage::Transfer { progress });
+    let filter = PathFilter::new(&arguments.include_paths, &arguments.exclude_paths)
+        .map_err(|error| RequestError(ErrorKind::InvalidRequest, error))?;
+    let credentials = &config::get().credentials;
+    // held until everything is read, so the repo can't be fetched into or removed in between
+    let (repo, _guard) = fetch_or_clone(arguments.repo_link.clone(), credentials, on_progress, cancel).map_err(git_error)?;
+
+    on_event(ServerMessage::Progress { stage: "Reading commits".to_string(), percent: None });
+    let release_commit = resolve_commit(&repo, &arguments.release_tag).map_err(|error| RequestError(ErrorKind::Git, error))?;
+    let prev_release_commit = resolve_commit(&repo, &arguments.prev_release_tag).map_err(|error| RequestError(ErrorKind::Git, error))?;
+    let commits = read_commits(&repo, &arguments.release_tag, &arguments.prev_release_tag, &filter, credentials, cancel)
+        .map_err(git_error)?;
+    let (commits, removed) = clean_up_history(&repo, commits, &arguments.release_tag, &arguments.prev_release_tag, credentials, cancel)
+        .map_err(git_error)?;
+    on_event(ServerMessage::Cleanup { removed });
+
+    let mut contributors = vec![];
+    if arguments.credit_contributors {
+        on_event(ServerMessage::Progress { stage: "Reading contributors".to_string(), percent: None });
+        let bots = BotFilter::new(&config::get().contributors.bot_patterns).map_err(|error| RequestError(ErrorKind::Internal, error))?;
+        contributors = read_contributors(&repo, &commits, &arguments.release_tag, &arguments.prev_release_tag, &bots, credentials, cancel)
+            .map_err(git_error)?;
+    }
+
+    let highlights = read_highlights(&repo, &commits, &arguments.release_tag, arguments.include_notes, credentials, cancel)
+        .map_err(git_error)?;
+
+    return Ok(Release { release_commit, prev_release_commit, commits, contributors, highlights });
+}
+
+async fn handle_request(
+    arguments: Arguments,
+    sender: mpsc::UnboundedSender<ServerMessage>,
+    cancel: CancelToken
+) -> Result<(), RequestError> {
+    if arguments.any_field_empty() {
+        return Err(RequestError(ErrorKind::InvalidRequest, anyhow!("A field has been left empty.")));
+    }
+
+    let on_event = |message: ServerMessage| {
+        let _ = sender.send(message);
+    };
+
+    // the server starts without a usable LLM configuration, which fails the generation before anything is cloned
+    let llm_config = &config::get().llm;
+    let provider = llm_config.provider().map_err(|error| RequestError(ErrorKind::Llm, error))?;
+    let provider = Metered::new(provider.as_ref());
+
+    let git_arguments = arguments.clone();
+    let git_sender = sender.clone();
+    let Release { release_commit, prev_release_commit, commits, contributors, highlights } = tokio::task::spawn_blocking(move || {
+        let on_event = |message| {
+            let _ = git_sender.send(message);
+        };
+        return read_release(&git_arguments, on_event, &cancel);
+    })
+        .await
+        .map_err(|error| RequestError(ErrorKind::Internal, error.into()))??;
+    on_event(ServerMessage::CommitsFound { count: commits.len() });
+    let classifier = Classifier::new(&config::get().commits.fallback_patterns)
+        .map_err(|error| RequestError(ErrorKind::Internal, error))?;
+    let commits = classifier.categorize(&commits);
+
+    let mut release_notes = generate_release_notes(llm_config, &provider, &arguments, &highlights, commits, on_event)
+        .await
+        .map_err(|error| RequestError(ErrorKind::Llm, error))?;
+    release_notes.contributors = contributors;
+
+    let run = Run {
+        id: 0,
+        created_at: chrono::Utc::now(),
+        arguments,
+        release_commit,
+        prev_release_commit,
+        prompt_version: config::get().templates.version(),
+        model: provider.model().to_string(),
+        release_notes
+    };
+    // the notes are still worth showing if they can't be kept
+    let saved = run.clone();
+    if let Err(error) = History::blocking(move |history| history.save(&saved)).await {
+        on_event(ServerMessage::Warning { message: format!("The release notes couldn't be saved to the history: {error}") });
+    }
+
+    on_event(ServerMessage::Notes { release_notes: run.release_notes });
+    on_event(ServerMessage::Done { usage: provider.usage() });
+
+    Ok(())
+}
+
+// shared by every way of starting a generation, limits.max_concurrent_jobs applies to all of them together
+fn generation_permits() -> &'static Semaphore {
+    static PERMITS: OnceLock<Semaphore> = OnceLock::new();
+    return PERMITS.get_or_init(|| Semaphore::new(config::get().limits.max_concurrent_jobs));
+}
+
+// waits for its turn before handling the request
+async fn queue_request(
+    arguments: Arguments,
+    sender: mpsc::UnboundedSender<ServerMessage>,
+    cancel: CancelToken
+) -> Result<(), RequestError> {
+    let permits = generation_permits();
+    let _permit = match permits.try_acquire() {
+        Ok(permit) => permit,
+        Err(_) => {
+            let _ = sender.send(ServerMessage::Progress { stage: "Waiting for other generations to finish".to_string(), percent: None });
+            permits.acquire().await.expect("The semaphore is never closed.")
+        }
+    };
+
+    return handle_request(arguments, sender, cancel).await;
+}
+
+// runs the request in the background, the returned channel yields its messages and closes after the last one,
+// which is either Done or Error. the request stops once cancel is cancelled or the channel is dropped
+pub fn spawn_request(arguments: Arguments, cancel: CancelToken) -> mpsc::UnboundedReceiver<ServerMessage> {
+    let (sender, recv) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let metrics = Metrics::global();
+        metrics.started();
+        let started_at = Instant::now();
+        let release = format!("{} {}", arguments.product_name, arguments.release_tag);
+
+        // a separate task, so a panic is reported as an error as well
+        let mut request = tokio::spawn(queue_request(arguments, sender.clone(), cancel.clone()));
+        let cancelled = async {
+            tokio::select! {
+                _ = cancel.cancelled() => {},
+                // nobody is left to receive the messages, such as a REST client that disconnected
+                _ = sender.closed() => cancel.cancel("the client disconnected")
+            }
+        };
+        let error = tokio::select! {
+            result = &mut request => match result {
+                Ok(Ok(())) => None,
+                Ok(Err(RequestError(kind, error))) => Some((kind, error.to_string())),
+                Err(error) => Some((ErrorKind::Internal, error.to_string()))
             },
-            Err(error) => {
-                return Err(anyhow!("Error fetching tokens: {error}"));
+            // dropping the request drops the model's event stream, which closes the connection to the API.
+            // a clone or fetch on the blocking thread sees the token and aborts its transfer
+            _ = cancelled => {
+                request.abort();
+                Some((ErrorKind::Cancelled, Cancelled.to_string()))
             }
+        };
+
+        metrics.finished(error.as_ref().map(|(kind, _)| *kind));
+        let Some((kind, message)) = error else {
+            return;
+        };
+        if kind == ErrorKind::Cancelled {
+            leptos::logging::log!(
+                "Generation of {release} cancelled after {:.1}s: {}",
+                started_at.elapsed().as_secs_f64(),
+                cancel.reason().unwrap_or_else(|| "the clone was aborted".to_string()));
         }
-    }
+        let _ = sender.send(ServerMessage::Error { kind, message });
+    });
 
-    Ok(())
+    return recv;
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -72,16 +329,16 @@ enum ParseError {
     #[error("Unable to parse message.")]
     UnsupportedFormat,
     #[error("Unable to parse message.")]
-    InvalidArguments
+    InvalidRequest
 }
 
-async fn parse_arguments(socket: &mut WebSocket) -> Result<Arguments, ParseError> {
+async fn parse_request(socket: &mut WebSocket) -> Result<ClientMessage, ParseError> {
     match socket.recv().await {
         Some(Ok(Message::Text(message))) => {
-            if let Ok(arguments) = serde_json::from_str::<Arguments>(&message) {
-                return Ok(arguments);
+            if let Ok(request) = serde_json::from_str::<ClientMessage>(&message) {
+                return Ok(request);
             } else {
-                return Err(ParseError::InvalidArguments);
+                return Err(ParseError::InvalidRequest);
             }
         },
         Some(Ok(Message::Close(_))) => {
@@ -100,60 +357,68 @@ async fn parse_arguments(socket: &mut WebSocket) -> Result<Arguments, ParseError
     }
 }
 
+async fn send_message(socket: &mut WebSocket, message: ServerMessage) {
+    let serialized = serde_json::to_string(&Envelope::new(message))
+        .expect("Serializing a server message should always succeed.");
+    // purposefully ignore an error since the error message would need to reach the client with the broken socket
+    let _ = socket.send(Message::Text(serialized)).await;
+}
+
 async fn handle_socket(mut socket: WebSocket) {
-    let arguments;
-    match parse_arguments(&mut socket).await {
-        Ok(args) => arguments = args,
+    let request = match parse_request(&mut socket).await {
+        Ok(request) => request,
         Err(ParseError::CloseRequest) => {
             return;
         }
         Err(error) => {
-            let _ = socket.send(Message::Text(anyhow!("{error}").to_string())).await;
+            send_message(&mut socket, ServerMessage::Error { kind: ErrorKind::InvalidRequest, message: error.to_string() }).await;
             return;
         }
-    }
+    };
 
-    let (sender, mut recv) = mpsc::unbounded_channel();         // for output tokens
-    let mut handle = tokio::spawn(handle_request(arguments, sender));
+    // the job keeps running when the connection is lost, so the client can attach to it again
+    let job = match request {
+        ClientMessage::Start { arguments } => Jobs::global().start(arguments),
+        ClientMessage::Attach { job_id } => match Jobs::global().get(&job_id) {
+            Some(job) => job,
+            None => {
+                let message = format!("Job {job_id} doesn't exist or has expired.");
+                send_message(&mut socket, ServerMessage::Error { kind: ErrorKind::InvalidRequest, message }).await;
+                return;
+            }
+        },
+        ClientMessage::Cancel => {
+            let message = "Cancel can only follow start or attach.".to_string();
+            send_message(&mut socket, ServerMessage::Error { kind: ErrorKind::InvalidRequest, message }).await;
+            return;
+        }
+    };
+    let _attachment = job.attach();
+    send_message(&mut socket, ServerMessage::Job { id: job.id.clone() }).await;
 
+    let mut cursor = Cursor::default();
     loop {
         // the server must respond to the events below
         tokio::select! {
-            // when there is an output token, it should be immediately sent to the client
-            Some(token) = recv.recv() => {
-                let serialized_ok = serde_json::to_string(&Ok::<String, String>(token))
-                    .expect("Serializing Result<String, String> should always succeed.");
-                // purposefully ignore an error since the error message would need to reach the client with the broken socket
-                let _ = socket.send(Message::Text(serialized_ok)).await;
-            }
-            // if it receives a close message from the client, the server must end the connection
-            Some(Ok(Message::Close(_))) = socket.next() => {
-                break;
+            // every message should be immediately sent to the client, starting with the ones the job had before
+            message = job.message(&mut cursor) => {
+                match message {
+                    Some(message) => send_message(&mut socket, message).await,
+                    None => break
+                }
             }
-            // if the request has been handled or there is an error, close the connection
-            result = &mut handle => {
-                match result {
-                    Ok(Ok(())) => {
-                        // for some reason, handle sometimes enters the event queue before the last token does
-                        // This is to flush all tokens out from the sender
-                        while let Some(token) = recv.recv().await {
-                            let serialized_ok = serde_json::to_string(&Ok::<String, String>(token))
-                                .expect("Serializing Result<String, String> should always succeed.");
-                            let _ = socket.send(Message::Text(serialized_ok)).await;
+            message = socket.next() => {
+                match message {
+                    Some(Ok(Message::Text(message))) => {
+                        if let Ok(ClientMessage::Cancel) = serde_json::from_str(&message) {
+                            job.cancel("cancelled by the client");
+                            break;
                         }
                     },
-                    Ok(Err(error)) => {
-                        let serialized_error = serde_json::to_string(&Err::<String, String>(error.to_string()))
-                            .expect("Serializing Result<String, String> should always succeed.");
-                        let _ = socket.send(Message::Text(serialized_error)).await;
-                    },
-                    Err(error) => {
-                        let serialized_error = serde_json::to_string(&Err::<String, String>(error.to_string()))
-                            .expect("Serializing Result<String, String> should always succeed.");
-                        let _ = socket.send(Message::Text(serialized_error)).await;
-                    }
+                    // if it receives a close message from the client, the server must end the connection
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
                 }
-                break;
             }
         }
     }
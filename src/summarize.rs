@@ -0,0 +1,306 @@
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+
+use crate::commits::{components_to_prompt, CategorizedCommits, Component};
+use crate::fetch_tokens::{Provider, ResponseFormat, TokenStream};
+use crate::util::ServerMessage;
+
+// how often intermediate notes are merged before giving up on fitting them into the final prompt
+const MAX_MERGE_ROUNDS: usize = 4;
+
+// a rough estimate, tokenizers average about four characters per token on english text and code
+pub fn estimate_tokens(text: &str) -> usize {
+    return text.len().div_ceil(4);
+}
+
+pub enum Changes {
+    Commits(CategorizedCommits),
+    // intermediate notes written for batches of commits when all of them don't fit into a single prompt
+    // the components are those of all the commits, which the summaries can't keep track of
+    Summaries { summaries: Vec<String>, breaking_changes: bool, components: Vec<Component> }
+}
+
+impl Changes {
+    pub fn has_breaking_changes(&self) -> bool {
+        match self {
+            Changes::Commits(commits) => commits.breaking_changes().next().is_some(),
+            Changes::Summaries { breaking_changes, .. } => *breaking_changes
+        }
+    }
+
+    pub fn to_prompt(&self) -> String {
+        match self {
+            Changes::Commits(commits) => return commits_to_prompt(commits),
+            Changes::Summaries { summaries, components, .. } => return summaries_to_prompt(summaries, components)
+        }
+    }
+}
+
+// what Changes::to_prompt gives for the commits or summaries, so condense can measure it without building one
+fn commits_to_prompt(commits: &CategorizedCommits) -> String {
+    return with_components(&commits.components(), format!("Commits grouped by category:\n{}", commits.to_prompt()));
+}
+
+fn summaries_to_prompt(summaries: &[String], components: &[Component]) -> String {
+    let changes = format!("Intermediate release notes, each covering a batch of the commits:\n{}",
+        summaries.join("\n--------------------\n"));
+    return with_components(components, changes);
+}
+
+fn with_components(components: &[Component], changes: String) -> String {
+    return match components_to_prompt(components) {
+        Some(components) => format!("{components}\n\n{changes}"),
+        None => changes
+    };
+}
+
+pub async fn collect_tokens<F>(mut token_stream: TokenStream, on_token: F) -> Result<String> where F: Fn(&str) {
+    let mut output = String::new();
+    while let Some(token) = token_stream.next().await {
+        match token {
+            Ok(Some(token)) => {
+                if !token.is_empty() {
+                    on_token(&token);
+                }
+                output += &token;
+            },
+            Ok(None) => break,
+            Err(error) => return Err(anyhow!("Error fetching tokens: {error}"))
+        }
+    }
+
+    return Ok(output);
+}
+
+fn batch_commits(commits: CategorizedCommits, budget: usize) -> Vec<CategorizedCommits> {
+    let mut batches = vec![];
+    let mut batch = CategorizedCommits::default();
+    let mut batch_tokens = 0;
+
+    for mut commit in [commits.features, commits.enhancements, commits.fixes, commits.other].into_iter().flatten() {
+        let mut tokens = estimate_tokens(&commit.to_prompt());
+        // a single enormous commit message is cut down to half a batch rather than overflowing one
+        if tokens > budget {
+            commit.body = commit.body.chars().take(budget * 2).collect();
+            tokens = estimate_tokens(&commit.to_prompt());
+        }
+
+        if batch_tokens + tokens > budget && !batch.is_empty() {
+            batches.push(std::mem::take(&mut batch));
+            batch_tokens = 0;
+        }
+        batch.push(commit);
+        batch_tokens += tokens;
+    }
+
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+
+    return batches;
+}
+
+fn batch_summaries(summaries: Vec<String>, budget: usize) -> Vec<Vec<String>> {
+    let mut batches: Vec<Vec<String>> = vec![];
+    let mut batch_tokens = 0;
+
+    for summary in summaries {
+        let tokens = estimate_tokens(&summary);
+        match batches.last_mut() {
+            Some(batch) if batch_tokens + tokens <= budget => batch.push(summary),
+            _ => {
+                batches.push(vec![summary]);
+                batch_tokens = 0;
+            }
+        }
+        batch_tokens += tokens;
+    }
+
+    return batches;
+}
+
+// map-reduce over the commits: batches that fit into batch_budget are summarized separately and the summaries
+// are merged until they fit into final_budget, the room left in the final prompt. summarize_prompt is the system
+// prompt for both
+pub async fn condense<F>(
+    provider: &dyn Provider,
+    summarize_prompt: &str,
+    commits: CategorizedCommits,
+    batch_budget: usize,
+    final_budget: usize,
+    on_event: F
+) -> Result<Changes> where F: Fn(ServerMessage) {
+    // measured as they will be sent, with the components
+    if estimate_tokens(&commits_to_prompt(&commits)) <= final_budget {
+        return Ok(Changes::Commits(commits));
+    }
+
+    let breaking_changes = commits.breaking_changes().next().is_some();
+    let components = commits.components();
+    let batches = batch_commits(commits, batch_budget);
+    let batch_count = batches.len();
+    on_event(ServerMessage::Warning {
+        message: format!("The commits don't fit into a single prompt, so they are summarized in {batch_count} batches first.")
+    });
+    let mut summaries = vec![];
+    for (i, batch) in batches.into_iter().enumerate() {
+        on_event(ServerMessage::Progress {
+            stage: format!("Summarizing batch {} of {batch_count}", i + 1),
+            percent: Some((i * 100 / batch_count) as u8)
+        });
+        let prompt = Changes::Commits(batch).to_prompt();
+        summaries.push(collect_tokens(provider.fetch_tokens(&prompt, summarize_prompt, ResponseFormat::Text), |_| {}).await?);
+    }
+
+    let mut rounds = 0;
+    while estimate_tokens(&summaries_to_prompt(&summaries, &components)) > final_budget {
+        rounds += 1;
+        if rounds > MAX_MERGE_ROUNDS {
+            return Err(anyhow!("The release is too large to fit into the context window."));
+        }
+
+        let batches = batch_summaries(summaries, batch_budget);
+        let batch_count = batches.len();
+        summaries = vec![];
+        for (i, batch) in batches.into_iter().enumerate() {
+            on_event(ServerMessage::Progress {
+                stage: format!("Merging summaries {} of {batch_count}", i + 1),
+                percent: Some((i * 100 / batch_count) as u8)
+            });
+            let prompt = Changes::Summaries { summaries: batch, breaking_changes, components: vec![] }.to_prompt();
+            summaries.push(collect_tokens(provider.fetch_tokens(&prompt, summarize_prompt, ResponseFormat::Text), |_| {}).await?);
+        }
+    }
+
+    return Ok(Changes::Summaries { summaries, breaking_changes, components });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commits::Classifier;
+    use crate::fetch_tokens::Mock;
+    use crate::git::{CommitInfo, DiffStat, PathStat};
+    use futures::executor::block_on;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn commit(message: &str) -> CommitInfo {
+        return CommitInfo {
+            id: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            author: "Jane Doe".to_string(),
+            author_email: "jane@example.com".to_string(),
+            date: chrono::DateTime::default(),
+            message: message.to_string(),
+            diffstat: DiffStat::default()
+        };
+    }
+
+    // "- " and a 38 character description, 10 tokens each
+    fn commits(count: usize) -> CategorizedCommits {
+        let commits = (0..count)
+            .map(|i| commit(&format!("fix: {i:0>38}")))
+            .collect::<Vec<_>>();
+        return Classifier::default().categorize(&commits);
+    }
+
+    // answers every prompt with the same text and counts the prompts
+    struct Counting {
+        mock: Mock,
+        calls: AtomicUsize
+    }
+
+    impl Provider for Counting {
+        fn model(&self) -> &str {
+            "counting"
+        }
+
+        fn fetch_tokens(&self, prompt: &str, system_prompt: &str, format: ResponseFormat) -> TokenStream {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            return self.mock.fetch_tokens(prompt, system_prompt, format);
+        }
+    }
+
+    fn counting(response: &str) -> Counting {
+        return Counting { mock: Mock { response: response.to_string(), ..Mock::default() }, calls: AtomicUsize::new(0) };
+    }
+
+    #[test]
+    fn estimates_four_characters_per_token() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+    }
+
+    #[test]
+    fn batches_commits_up_to_the_budget() {
+        assert_eq!(estimate_tokens(&commits(1).fixes[0].to_prompt()), 10);
+
+        // a batch that exactly fills the budget is kept
+        let sizes = batch_commits(commits(5), 20).iter().map(CategorizedCommits::len).collect::<Vec<_>>();
+        assert_eq!(sizes, [2, 2, 1]);
+        let sizes = batch_commits(commits(5), 19).iter().map(CategorizedCommits::len).collect::<Vec<_>>();
+        assert_eq!(sizes, [1, 1, 1, 1, 1]);
+        assert!(batch_commits(commits(0), 20).is_empty());
+    }
+
+    #[test]
+    fn cuts_down_commits_larger_than_a_batch() {
+        let mut commits = commits(2);
+        commits.fixes[0].body = "x".repeat(1000);
+        let batches = batch_commits(commits, 50);
+
+        // down to half a batch, which leaves room for the next commit
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].len(), 2);
+        assert_eq!(batches[0].fixes[0].body.len(), 100);
+        assert!(estimate_tokens(&batches[0].fixes[0].to_prompt()) <= 50);
+    }
+
+    #[test]
+    fn batches_summaries_up_to_the_budget() {
+        let summaries = vec!["x".repeat(40); 5];
+        let sizes = batch_summaries(summaries.clone(), 25).iter().map(Vec::len).collect::<Vec<_>>();
+        assert_eq!(sizes, [2, 2, 1]);
+
+        // one larger than the budget gets a batch of its own
+        let summaries = vec!["x".repeat(40), "x".repeat(400), "x".repeat(40)];
+        let sizes = batch_summaries(summaries, 25).iter().map(Vec::len).collect::<Vec<_>>();
+        assert_eq!(sizes, [1, 1, 1]);
+    }
+
+    #[test]
+    fn measures_the_components_as_well() {
+        let mut commit = commit(&format!("feat: add export\n\n{}", "x".repeat(800)));
+        commit.diffstat = DiffStat {
+            files_changed: 1,
+            insertions: 10,
+            deletions: 0,
+            paths: vec![PathStat { path: "src/".to_string(), insertions: 10, deletions: 0 }]
+        };
+        let commits = Classifier::default().categorize(&[commit]);
+        let budget = estimate_tokens(&commits.to_prompt());
+        assert!(estimate_tokens(&commits_to_prompt(&commits)) > budget);
+
+        let provider = counting("- Export");
+        let changes = block_on(condense(&provider, "", commits, 1000, budget, |_| {})).unwrap();
+        assert!(matches!(changes, Changes::Summaries { .. }));
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn keeps_commits_that_fit() {
+        let provider = counting("- Fixes");
+        let changes = block_on(condense(&provider, "", commits(3), 1000, 1000, |_| {})).unwrap();
+        assert!(matches!(changes, Changes::Commits(commits) if commits.len() == 3));
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn gives_up_after_the_last_merge_round() {
+        // the summaries never get shorter, so every round merges them into one that still doesn't fit
+        let provider = counting(&"word ".repeat(100));
+        let error = block_on(condense(&provider, "", commits(3), 1000, 20, |_| {})).err().expect("The release should not fit.");
+        assert_eq!(error.to_string(), "The release is too large to fit into the context window.");
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 1 + MAX_MERGE_ROUNDS);
+    }
+}
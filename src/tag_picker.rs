@@ -0,0 +1,56 @@
+use leptos::*;
+
+use crate::util::TagInfo;
+
+#[server(ListTags, "/api")]
+pub async fn list_tags(repo_link: String) -> Result<Vec<TagInfo>, ServerFnError> {
+    use crate::cancel::CancelToken;
+    use crate::config;
+    use crate::git;
+    use crate::util::sort_tags;
+
+    // kept off the async runtime like clones for generations, the form only shows that tags are loading
+    let mut tags = tokio::task::spawn_blocking(move || {
+        let (repo, _guard) = git::fetch_or_clone(repo_link, &config::get().credentials, |_| {}, &CancelToken::default())?;
+        return git::list_tags(&repo);
+    })
+        .await
+        .map_err(ServerFnError::new)?
+        .map_err(ServerFnError::new)?;
+    sort_tags(&mut tags);
+
+    return Ok(tags);
+}
+
+// a text input backed by a datalist, so tags can be searched by typing but are still suggested from the repo
+#[component]
+pub fn TagPicker<F>(
+    list_id: &'static str,
+    tag: ReadSignal<String>,
+    on_pick: F,
+    tags: Signal<Vec<TagInfo>>
+) -> impl IntoView where F: Fn(String) + 'static {
+    view! {
+        <input
+            class="w-[10em] px-[3px] text-[1rem] bg-gray-200 border-2 border-black"
+            type="text"
+            list=list_id
+            value={tag}
+            on:input = move |event| on_pick(event_target_value(&event)) />
+        <datalist id=list_id>
+            <For
+                each=tags
+                key=|tag| tag.name.clone()
+                children=move |tag| {
+                    let date = tag.date
+                        .map(|date| date.format("%Y-%m-%d").to_string())
+                        .unwrap_or_default();
+                    let label = format!("{date} {}", tag.target.get(..7).unwrap_or(&tag.target));
+                    view! {
+                        <option value=tag.name>{label}</option>
+                    }
+                }
+            />
+        </datalist>
+    }
+}
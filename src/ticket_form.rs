@@ -2,12 +2,15 @@ use leptos::*;
 
 use crate::util::Ticket;
 
+// every ticket of the form with the id it was added under
+pub type TicketSignals = Vec<(usize, (ReadSignal<Ticket>, WriteSignal<Ticket>))>;
+
 #[component]
 pub fn TicketForm(
     ticket: ReadSignal<Ticket>,
     set_ticket: WriteSignal<Ticket>,
-    tickets: ReadSignal<Vec<(usize, (ReadSignal<Ticket>, WriteSignal<Ticket>))>>,
-    set_tickets: WriteSignal<Vec<(usize, (ReadSignal<Ticket>, WriteSignal<Ticket>))>>,
+    tickets: ReadSignal<TicketSignals>,
+    set_tickets: WriteSignal<TicketSignals>,
     id: usize
 ) -> impl IntoView {
     let delete_ticket = move |_| {
@@ -28,7 +31,7 @@ pub fn TicketForm(
         <div class="relative p-4 text-[0.9rem] border-2 border-black">
             <button
                 class="absolute top-0 right-0 m-2 px-[0.3em] py-[0.15em] border-2 border-black hover:bg-gray-200"
-                style:display=move || (tickets().len() <= 1).then(|| "None")
+                style:display=move || (tickets().len() <= 1).then_some("None")
                 on:click=delete_ticket
             >"Remove"</button>
             <div class="grid grid-cols-full gap-4 p-[0.75vw]">
@@ -42,6 +45,27 @@ pub fn TicketForm(
                             ticket.summary = event_target_value(&event)
                         ) />
                 </div>
+                <div class="grid grid-cols-[max-content_1fr] gap-2">
+                    <p class="text-[0.95rem]">"ID"</p>
+                    <input
+                        class="w-[10em] px-[3px] bg-gray-200 border-2 border-black"
+                        type = "text"
+                        placeholder = "PROJ-456 or #123"
+                        value=move || ticket().id.unwrap_or_default()
+                        on:input = move |event| set_ticket.update(|ticket| {
+                            let id = event_target_value(&event);
+                            ticket.id = (!id.trim().is_empty()).then_some(id);
+                        }) />
+                    <p class="text-[0.95rem]">"URL"</p>
+                    <input
+                        class="w-full px-[3px] bg-gray-200 border-2 border-black"
+                        type = "text"
+                        value=move || ticket().url.unwrap_or_default()
+                        on:input = move |event| set_ticket.update(|ticket| {
+                            let url = event_target_value(&event);
+                            ticket.url = (!url.trim().is_empty()).then_some(url);
+                        }) />
+                </div>
                 <div>
                     <p class="text-[0.95rem]">"Description"</p>
                     <textarea
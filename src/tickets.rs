@@ -0,0 +1,190 @@
+use anyhow::{anyhow, Result};
+use regex::Regex;
+
+use crate::commits::{CategorizedCommits, ClassifiedCommit};
+use crate::util::{Ticket, TicketLinkReport};
+
+// "#123" and "Fixes #78" as used by GitHub and GitLab, "PROJ-456" as used by Jira and Linear
+pub const DEFAULT_REFERENCE_PATTERNS: &[&str] = &[r"#(?<id>\d+)\b", r"\b(?<id>[A-Z][A-Z0-9]+-\d+)\b"];
+
+// "#78" and "proj-456" refer to the same tickets as "78" and "PROJ-456"
+fn normalize(id: &str) -> String {
+    return id.trim().trim_start_matches('#').to_uppercase();
+}
+
+pub struct LinkedTicket {
+    pub ticket: Ticket,
+    pub commits: Vec<String>            // the descriptions of the commits referencing the ticket
+}
+
+impl LinkedTicket {
+    pub fn to_prompt(&self) -> String {
+        let Ticket { summary, description, id, url } = &self.ticket;
+        let mut prompt = String::new();
+        if let Some(id) = id {
+            prompt += &format!("ID:{id}\n");
+        }
+        if let Some(url) = url {
+            prompt += &format!("URL:{url}\n");
+        }
+        prompt += &format!("Summary:{summary}\nDescription:{description}");
+
+        if !self.commits.is_empty() {
+            let commits = self.commits
+                .iter()
+                .map(|commit| format!("- {commit}"))
+                .collect::<Vec<_>>()
+                .join("\n");
+            prompt += &format!("\nImplemented by the commits:\n{commits}");
+        }
+
+        return prompt;
+    }
+}
+
+pub struct TicketLinks {
+    pub tickets: Vec<LinkedTicket>,
+    pub report: TicketLinkReport
+}
+
+pub struct TicketLinker {
+    patterns: Vec<Regex>
+}
+
+impl TicketLinker {
+    // every pattern needs a group named id, which is what is compared to the ids of the tickets
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<Self> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                let pattern = pattern.as_ref();
+                let regex = Regex::new(pattern).map_err(|error| anyhow!("Invalid reference pattern {pattern:?}: {error}"))?;
+                if !regex.capture_names().any(|name| name == Some("id")) {
+                    return Err(anyhow!("The reference pattern {pattern:?} has no group named id."));
+                }
+                return Ok(regex);
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        return Ok(TicketLinker { patterns });
+    }
+
+    // the normalized ids the commit refers to, from its subject and its body
+    pub fn references(&self, commit: &ClassifiedCommit) -> Vec<String> {
+        let mut references = vec![];
+        for text in [&commit.description, &commit.body] {
+            for pattern in &self.patterns {
+                for captures in pattern.captures_iter(text) {
+                    let reference = normalize(&captures["id"]);
+                    if !references.contains(&reference) {
+                        references.push(reference);
+                    }
+                }
+            }
+        }
+
+        return references;
+    }
+
+    // commits that reference no ticket and tickets no commit references are reported, tickets without an id
+    // can't be referenced and are reported as well
+    pub fn link(&self, tickets: &[Ticket], commits: &CategorizedCommits) -> TicketLinks {
+        let mut linked = tickets
+            .iter()
+            .map(|ticket| LinkedTicket { ticket: ticket.clone(), commits: vec![] })
+            .collect::<Vec<_>>();
+        let ids = tickets
+            .iter()
+            .map(|ticket| ticket.id.as_deref().map(normalize).filter(|id| !id.is_empty()))
+            .collect::<Vec<_>>();
+
+        let mut report = TicketLinkReport::default();
+        for commit in commits.iter() {
+            let references = self.references(commit);
+            let mut found = false;
+            for (ticket, id) in linked.iter_mut().zip(&ids) {
+                if id.as_ref().is_some_and(|id| references.contains(id)) {
+                    ticket.commits.push(commit.description.clone());
+                    found = true;
+                }
+            }
+            if !found {
+                report.unlinked_commits.push(commit.description.clone());
+            }
+        }
+        report.unlinked_tickets = linked
+            .iter()
+            .filter(|ticket| ticket.commits.is_empty())
+            .map(|ticket| ticket.ticket.id.clone().unwrap_or_else(|| ticket.ticket.summary.clone()))
+            .collect();
+
+        return TicketLinks { tickets: linked, report };
+    }
+}
+
+impl Default for TicketLinker {
+    fn default() -> Self {
+        return TicketLinker::new(DEFAULT_REFERENCE_PATTERNS)
+            .expect("The default reference patterns should always compile.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commits::Classifier;
+    use crate::git::{CommitInfo, DiffStat};
+
+    fn commit(message: &str) -> CommitInfo {
+        return CommitInfo {
+            id: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            author: "Jane Doe".to_string(),
+            author_email: "jane@example.com".to_string(),
+            date: chrono::DateTime::default(),
+            message: message.to_string(),
+            diffstat: DiffStat::default()
+        };
+    }
+
+    fn ticket(id: Option<&str>, summary: &str) -> Ticket {
+        return Ticket { summary: summary.to_string(), description: String::new(), id: id.map(String::from), url: None };
+    }
+
+    #[test]
+    fn references_are_normalized() {
+        let classified = Classifier::default().classify(&commit("fix: handle empty input (#78)\n\nCloses PROJ-456, see #78."));
+        assert_eq!(TicketLinker::default().references(&classified), ["78", "PROJ-456"]);
+    }
+
+    #[test]
+    fn lowercase_keys_are_not_references() {
+        let classified = Classifier::default().classify(&commit("fix: handle proj-456"));
+        assert!(TicketLinker::default().references(&classified).is_empty());
+    }
+
+    #[test]
+    fn links_ids_written_either_way() {
+        let commits = Classifier::default().categorize(&[
+            commit("feat: add export\n\nFixes #78."),
+            commit("fix: handle empty input (PROJ-456)"),
+            commit("chore: bump dependencies")
+        ]);
+        let tickets = [ticket(Some("78"), "Export"), ticket(Some("proj-456"), "Empty input"), ticket(None, "Faster startup")];
+        let links = TicketLinker::default().link(&tickets, &commits);
+
+        assert_eq!(links.tickets[0].commits, ["add export"]);
+        assert_eq!(links.tickets[1].commits, ["handle empty input (PROJ-456)"]);
+        assert!(links.tickets[2].commits.is_empty());
+        assert_eq!(links.report, TicketLinkReport {
+            unlinked_commits: vec!["bump dependencies".to_string()],
+            unlinked_tickets: vec!["Faster startup".to_string()]
+        });
+    }
+
+    #[test]
+    fn patterns_need_an_id_group() {
+        assert!(TicketLinker::new(&[r"#\d+"]).is_err());
+        assert!(TicketLinker::new(&[r"#(?<id>\d+"]).is_err());
+        assert!(TicketLinker::new(&[r"GH-(?<id>\d+)"]).is_ok());
+    }
+}
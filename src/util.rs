@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum TargetAudience {
@@ -17,9 +18,14 @@ impl Default for TargetAudience {
 pub struct Ticket {
     pub summary: String,
     pub description: String,
+    // commits referencing the id, such as #123 or PROJ-456, are linked to the ticket
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub url: Option<String>
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Arguments {
     pub repo_link: String,
     pub product_name: String,
@@ -27,12 +33,24 @@ pub struct Arguments {
     pub prev_release_tag: String,
     pub release_date: chrono::NaiveDate,
     pub target_audience: TargetAudience,
-    pub tickets: Vec<Ticket>
+    pub tickets: Vec<Ticket>,
+    // pathspecs such as services/billing/**, for notes on one component of a monorepo. only commits changing a
+    // path that matches an include pathspec, or any path without them, and no exclude pathspec are kept
+    #[serde(default)]
+    pub include_paths: Vec<String>,
+    #[serde(default)]
+    pub exclude_paths: Vec<String>,
+    // adds the people who authored, co-authored or committed the changes to the notes
+    #[serde(default)]
+    pub credit_contributors: bool,
+    // passes the git notes of the commits to the prompt along with the message of the release tag
+    #[serde(default)]
+    pub include_notes: bool
 }
 
 impl Arguments {
     pub fn any_field_empty(&self) -> bool {
-        let Arguments { repo_link, product_name, release_tag, prev_release_tag, release_date: _, target_audience: _, tickets } = self;
+        let Arguments { repo_link, product_name, release_tag, prev_release_tag, tickets, .. } = self;
 
         if tickets.is_empty() {
             return true;
@@ -43,7 +61,7 @@ impl Arguments {
             .into_iter()
             .chain(tickets
                 .iter()
-                .flat_map(|Ticket {summary, description}|
+                .flat_map(|Ticket {summary, description, ..}|
                     vec![summary, description])
         ) {
             if field.is_empty() {
@@ -53,4 +71,349 @@ impl Arguments {
 
         return false;
     }
-}
\ No newline at end of file
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ReleaseNote {
+    pub title: String,
+    pub description: String,
+    #[serde(default)]
+    pub references: Vec<String>         // the commits and tickets the item is based on
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Contributor {
+    pub name: String,
+    pub email: String,
+    pub commits: usize,
+    pub first_time: bool        // none of the commits of earlier releases are theirs
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ReleaseNotes {
+    // the header is filled in from the arguments rather than generated
+    #[serde(default)]
+    pub product_name: String,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub release_date: chrono::NaiveDate,
+    pub overview: String,
+    pub features: Vec<ReleaseNote>,
+    pub enhancements: Vec<ReleaseNote>,
+    pub fixes: Vec<ReleaseNote>,
+    // read from the commits rather than generated, empty unless the contributors are credited
+    #[serde(default)]
+    pub contributors: Vec<Contributor>
+}
+
+impl ReleaseNotes {
+    // the plain-text template
+    pub fn render(&self) -> String {
+        let ReleaseNotes { product_name, version, release_date, overview, features, enhancements, fixes, contributors } = self;
+
+        let render_section = |heading: &str, notes: &[ReleaseNote]| {
+            let items = match notes.is_empty() {
+                true => "None.".to_string(),
+                false => notes
+                    .iter()
+                    .map(|note| format!("{}: {}", note.title, note.description))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            format!("{heading}:\n\n{items}")
+        };
+
+        let mut sections = vec![
+            format!("{product_name} Release Notes - {version} - {release_date}"),
+            format!("Overview:\n\n{overview}"),
+            render_section("New Features", features),
+            render_section("Enhancements", enhancements),
+            render_section("Fixes", fixes)
+        ];
+        if !contributors.is_empty() {
+            let names = contributors
+                .iter()
+                .map(|contributor| match contributor.first_time {
+                    true => format!("{} (first contribution)", contributor.name),
+                    false => contributor.name.clone()
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            sections.push(format!("Contributors:\n\n{names}"));
+        }
+
+        return sections.join("\n\n");
+    }
+}
+
+// a past generation as listed on /history
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RunSummary {
+    pub id: i64,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub repo_link: String,
+    pub product_name: String,
+    pub release_tag: String,
+    pub prev_release_tag: String,
+    pub model: String
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Run {
+    pub id: i64,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub arguments: Arguments,
+    // the tags may be moved later, so the commits they pointed to are kept as well
+    pub release_commit: String,
+    pub prev_release_commit: String,
+    pub prompt_version: String,
+    pub model: String,
+    pub release_notes: ReleaseNotes
+}
+
+// bumped whenever a message changes in a way older clients can't handle
+pub const PROTOCOL_VERSION: u32 = 7;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    InvalidRequest,
+    RepoNotAllowed,
+    RepoTooLarge,
+    CloneTimedOut,
+    Git,
+    Llm,
+    Cancelled,
+    Internal
+}
+
+// estimated from the length of the prompts and responses, since not every provider reports usage while streaming
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Usage {
+    pub llm_calls: usize,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize
+}
+
+// how far a clone or fetch has come, deltas are only counted once every object has been received
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct TransferProgress {
+    pub received_objects: usize,
+    pub indexed_objects: usize,
+    pub total_objects: usize,
+    pub received_bytes: usize,
+    pub indexed_deltas: usize,
+    pub total_deltas: usize
+}
+
+impl TransferProgress {
+    // the phase the transfer is in, with how much of it is done out of how much
+    pub fn phase(&self) -> (&'static str, usize, usize) {
+        if self.total_deltas > 0 {
+            return ("Resolving deltas", self.indexed_deltas, self.total_deltas);
+        }
+        if self.received_objects < self.total_objects {
+            return ("Receiving objects", self.received_objects, self.total_objects);
+        }
+
+        return ("Indexing objects", self.indexed_objects, self.total_objects);
+    }
+
+    pub fn label(&self) -> String {
+        let (phase, done, total) = self.phase();
+        let megabytes = self.received_bytes as f64 / (1024.0 * 1024.0);
+        return format!("{phase} {done}/{total}, {megabytes:.1} MB");
+    }
+}
+
+// what couldn't be linked, commits by their description and tickets by their id or summary
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct TicketLinkReport {
+    pub unlinked_commits: Vec<String>,
+    pub unlinked_tickets: Vec<String>
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemovalReason {
+    Reverted,           // reverted later in the release
+    Revert,             // reverts a commit of the release
+    Fixup,              // a fixup! or squash! commit, collapsed into the commit it amends
+    AlreadyShipped      // the same change is in the previous release, such as a backported cherry-pick
+}
+
+// a commit the release notes leave out, related is the id of the commit that made it redundant
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RemovedCommit {
+    pub id: String,
+    pub subject: String,
+    pub reason: RemovalReason,
+    pub related: String
+}
+
+impl RemovedCommit {
+    pub fn describe(&self) -> String {
+        let related = self.related.get(..7).unwrap_or(&self.related);
+        return match self.reason {
+            RemovalReason::Reverted => format!("reverted by {related}"),
+            RemovalReason::Revert => format!("reverts {related}"),
+            RemovalReason::Fixup => format!("squashed into {related}"),
+            RemovalReason::AlreadyShipped => format!("already shipped as {related}")
+        };
+    }
+}
+
+// sent by the server on /submit, one per frame, always wrapped in an Envelope
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Job { id: String },                         // always the first message, the id can be used to attach again later
+    Progress { stage: String, percent: Option<u8> },
+    Transfer { progress: TransferProgress },    // sent repeatedly while the repository is cloned or fetched
+    Cleanup { removed: Vec<RemovedCommit> },     // commits left out of the release, sent before CommitsFound
+    CommitsFound { count: usize },
+    TicketLinks { report: TicketLinkReport },
+    Token { token: String },                    // raw output of the model while the release notes are generated
+    Warning { message: String },
+    Notes { release_notes: ReleaseNotes },
+    Error { kind: ErrorKind, message: String },
+    Done { usage: Usage }                       // always the last message after a successful request
+}
+
+// the first frame a client sends on /submit is Start or Attach, Cancel may follow it
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Start { arguments: Arguments },
+    // replays every message of a running or recently finished job, then follows it
+    Attach { job_id: String },
+    // stops the job for every client attached to it
+    Cancel
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Envelope {
+    pub version: u32,
+    #[serde(flatten)]
+    pub message: ServerMessage
+}
+
+impl Envelope {
+    pub fn new(message: ServerMessage) -> Self {
+        return Envelope { version: PROTOCOL_VERSION, message };
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TagInfo {
+    pub name: String,
+    pub date: Option<chrono::DateTime<chrono::Utc>>,    // the tagger date of annotated tags, otherwise the commit date
+    pub target: String                                  // id of the commit the tag points to
+}
+
+impl TagInfo {
+    pub fn version(&self) -> Option<semver::Version> {
+        let name = self.name.trim();
+        let name = name.strip_prefix(['v', 'V']).unwrap_or(name);
+        return semver::Version::parse(name).ok();
+    }
+}
+
+// semver tags come first, newest version first, followed by the remaining tags newest first
+pub fn sort_tags(tags: &mut [TagInfo]) {
+    tags.sort_by(|a, b| {
+        match (a.version(), b.version()) {
+            (Some(a_version), Some(b_version)) => b_version.cmp(&a_version),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => b.date.cmp(&a.date).then_with(|| a.name.cmp(&b.name))
+        }
+    });
+}
+
+// the tag that a release most likely follows, given tags sorted with sort_tags
+pub fn previous_tag<'a>(tags: &'a [TagInfo], release_tag: &str) -> Option<&'a TagInfo> {
+    let position = tags
+        .iter()
+        .position(|tag| tag.name == release_tag)?;
+    let release = &tags[position];
+
+    let Some(release_version) = release.version() else {
+        return tags[position + 1..].first();
+    };
+
+    // notes for a stable release should cover everything since the last stable release, skipping its release candidates
+    return tags[position + 1..]
+        .iter()
+        .find(|tag| tag.version().is_some_and(|version|
+            version < release_version && (version.pre.is_empty() || !release_version.pre.is_empty())));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(name: &str, day: u32) -> TagInfo {
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 1, day).unwrap().and_hms_opt(0, 0, 0).unwrap().and_utc();
+        return TagInfo { name: name.to_string(), date: Some(date), target: String::new() };
+    }
+
+    fn sorted(tags: &[(&str, u32)]) -> Vec<TagInfo> {
+        let mut tags = tags.iter().map(|(name, day)| tag(name, *day)).collect::<Vec<_>>();
+        sort_tags(&mut tags);
+        return tags;
+    }
+
+    fn names(tags: &[TagInfo]) -> Vec<&str> {
+        return tags.iter().map(|tag| tag.name.as_str()).collect();
+    }
+
+    #[test]
+    fn version_strips_the_v_prefix() {
+        assert_eq!(tag("v1.2.3", 1).version(), Some(semver::Version::new(1, 2, 3)));
+        assert_eq!(tag("V1.2.3", 1).version(), Some(semver::Version::new(1, 2, 3)));
+        assert_eq!(tag("1.2.3", 1).version(), Some(semver::Version::new(1, 2, 3)));
+        assert_eq!(tag("release-1", 1).version(), None);
+        assert_eq!(tag("v1.2", 1).version(), None);
+    }
+
+    #[test]
+    fn semver_tags_first_by_version() {
+        let tags = sorted(&[("nightly", 9), ("v1.10.0", 1), ("v1.9.0", 2), ("v2.0.0-rc.1", 3), ("v2.0.0", 4), ("beta", 5)]);
+        assert_eq!(names(&tags), ["v2.0.0", "v2.0.0-rc.1", "v1.10.0", "v1.9.0", "nightly", "beta"]);
+    }
+
+    #[test]
+    fn other_tags_newest_first_then_by_name() {
+        let tags = sorted(&[("a", 1), ("c", 2), ("b", 2)]);
+        assert_eq!(names(&tags), ["b", "c", "a"]);
+    }
+
+    #[test]
+    fn stable_release_skips_release_candidates() {
+        let tags = sorted(&[("v1.0.0", 1), ("v1.1.0-rc.1", 2), ("v1.1.0-rc.2", 3), ("v1.1.0", 4)]);
+        assert_eq!(previous_tag(&tags, "v1.1.0").map(|tag| tag.name.as_str()), Some("v1.0.0"));
+    }
+
+    #[test]
+    fn release_candidate_follows_the_previous_candidate() {
+        let tags = sorted(&[("v1.0.0", 1), ("v1.1.0-rc.1", 2), ("v1.1.0-rc.2", 3), ("v1.1.0", 4)]);
+        assert_eq!(previous_tag(&tags, "v1.1.0-rc.2").map(|tag| tag.name.as_str()), Some("v1.1.0-rc.1"));
+        assert_eq!(previous_tag(&tags, "v1.1.0-rc.1").map(|tag| tag.name.as_str()), Some("v1.0.0"));
+    }
+
+    #[test]
+    fn first_release_has_no_previous_tag() {
+        let tags = sorted(&[("v1.0.0", 1), ("v1.1.0", 2)]);
+        assert_eq!(previous_tag(&tags, "v1.0.0"), None);
+        assert_eq!(previous_tag(&tags, "v3.0.0"), None);
+    }
+
+    #[test]
+    fn previous_of_a_non_semver_tag_is_the_next_one() {
+        let tags = sorted(&[("v1.0.0", 1), ("build-2", 2), ("build-3", 3)]);
+        assert_eq!(previous_tag(&tags, "build-3").map(|tag| tag.name.as_str()), Some("build-2"));
+        assert_eq!(previous_tag(&tags, "build-2"), None);
+    }
+}